@@ -17,6 +17,13 @@ pub const PN_TIMEOUT: i8 = -7;
 pub const PN_INTR: i8 = -8;
 pub const PN_INPROGRESS: i8 = -9;
 
+// delivery states, after their descriptors
+pub const PN_RECEIVED: u64 = 0x23;
+pub const PN_ACCEPTED: u64 = 0x24;
+pub const PN_REJECTED: u64 = 0x25;
+pub const PN_RELEASED: u64 = 0x26;
+pub const PN_MODIFIED: u64 = 0x27;
+
 
 bitflags! {
     flags StateFlags: i32 {
@@ -1,94 +1,137 @@
 use mio::*;
 use mio::tcp::*;
-use mio::buf::{ByteBuf, MutByteBuf, SliceBuf};
 use mio::util::Slab;
 
 use std::io;
+use time;
 
 use proton;
 
 type AmqpEventLoop = EventLoop<AmqpHandler>;
 
+/// Milliseconds without hearing from a peer after which we hang up.
+const IDLE_TIMEOUT: u32 = 60000;
+
+fn now_ms() -> i64 {
+    (time::precise_time_ns() / 1000000) as i64
+}
+
 struct AmqpSocket {
     sock: NonBlock<TcpStream>,
-    buf: Option<ByteBuf>,
-    mut_buf: Option<MutByteBuf>,
     token: Option<Token>,
     interest: Interest,
+    // next call to Transport::tick
+    timeout: Option<Timeout>,
     connection: proton::Connection,
     transport: proton::Transport,
 }
 
 impl AmqpSocket {
     fn new(sock: NonBlock<TcpStream>) -> AmqpSocket {
-        let mut transport = proton::Transport::new();
+        let mut transport = proton::Transport::server();
         let mut connection = proton::Connection::new();
+        transport.set_idle_timeout(IDLE_TIMEOUT);
         transport.bind(&mut connection);
 
         AmqpSocket {
             sock: sock,
-            buf: None,
-            mut_buf:Some(ByteBuf::mut_with_capacity(2048)),
             token: None,
             interest: Interest::hup(),
+            timeout: None,
             connection: connection,
             transport: transport
         }
     }
 
     fn writable(&mut self, event_loop: &mut AmqpEventLoop) -> io::Result<()> {
-        let mut buf = self.buf.take().unwrap();
-
-        debug!("CON : writing buf = {:?}", buf.bytes());
-
-        match self.sock.write(&mut buf) {
-            Ok(None) => {
-                debug!("client flushing buf; WOULDBLOCK");
-                self.buf = Some(buf);
-                self.interest.insert(Interest::writable());
+        let written = match self.transport.head() {
+            Ok(head) => {
+                let written = self.sock.write_slice(&head);
+                if let Ok(Some(n)) = written {
+                    head.pop(n);
+                }
+                written
+            },
+            Err(e) => {
+                debug!("CONN : nothing more to write: {}", e);
+                Ok(None)
             }
-            Ok(Some(r)) => {
-                debug!("CONN : we wrote {} bytes!", r);
-                self.mut_buf = Some(buf.flip());
-                self.interest.insert(Interest::readable());
-                self.interest.remove(Interest::writable());
+        };
+
+        match written {
+            Ok(Some(n)) => debug!("CONN : we wrote {} bytes!", n),
+            Ok(None) => debug!("client flushing buf; WOULDBLOCK"),
+            Err(e) => {
+                debug!("CONN : write failed; client err={:?}", e);
+                let _ = self.transport.close_head();
             }
-            Err(e) => debug!("not implemented; client err={:?}", e),
         }
-        event_loop.reregister(&self.sock, self.token.unwrap(), self.interest, PollOpt::edge() | PollOpt::oneshot())
+        self.update(event_loop)
     }
 
     fn readable(&mut self, event_loop: &mut AmqpEventLoop) -> io::Result<()> {
-        let mut buf = self.mut_buf.take().unwrap();
-
-        match self.sock.read(&mut buf) {
-            Ok(None) => {
-                panic!("We just got readable, but were unable to read from the socket?");
-            }
-            Ok(Some(r)) => {
-                println!("CONN : we read {} bytes!", r);
-
-                //self.transport.push(buf.mut_bytes());
-                if !self.transport.has_capacity() {
-                    debug!("No capacity. Turning to writable");
-                    //self.interest.remove(Interest::readable());
-                    //self.interest.insert(Interest::writable());
+        let read = match self.transport.tail() {
+            Ok(mut tail) => {
+                let read = self.sock.read_slice(&mut tail);
+                if let Ok(Some(n)) = read {
+                    if n > 0 {
+                        if let Err(e) = tail.process(n) {
+                            debug!("CONN : transport error: {}", e);
+                        }
+                    }
                 }
-
-                self.interest.remove(Interest::readable());
-                self.interest.insert(Interest::writable());
-            }
+                read
+            },
             Err(e) => {
-                debug!("not implemented; client err={:?}", e);
-                self.interest.remove(Interest::readable());
+                debug!("CONN : nothing more to read: {}", e);
+                Ok(None)
             }
         };
 
-        self.buf = Some(buf.flip());
+        match read {
+            Ok(Some(0)) => {
+                debug!("CONN : peer hung up");
+                let _ = self.transport.close_tail();
+            },
+            Ok(Some(n)) => debug!("CONN : we read {} bytes!", n),
+            Ok(None) => {},
+            Err(e) => {
+                debug!("CONN : read failed; client err={:?}", e);
+                let _ = self.transport.close_tail();
+            }
+        }
+        self.update(event_loop)
+    }
+
+    /// Ticks the transport and waits for whatever it needs next: input,
+    /// room to write its output, or its next deadline.
+    fn update(&mut self, event_loop: &mut AmqpEventLoop) -> io::Result<()> {
+        let now = now_ms();
+        let deadline = self.transport.tick(now);
+
+        if let Some(timeout) = self.timeout.take() {
+            event_loop.clear_timeout(timeout);
+        }
+        if let Some(deadline) = deadline {
+            let delay = if deadline > now {(deadline - now) as u64} else {0};
+            match event_loop.timeout_ms(self.token.unwrap(), delay) {
+                Ok(timeout) => self.timeout = Some(timeout),
+                Err(_) => debug!("CONN : could not schedule the next tick")
+            }
+        }
+
+        self.interest = Interest::hup();
+        if self.transport.has_capacity().unwrap_or(false) {
+            self.interest.insert(Interest::readable());
+        }
+        if self.transport.pending().map(|pending| pending > 0).unwrap_or(false) {
+            self.interest.insert(Interest::writable());
+        }
+
         event_loop.reregister(&self.sock,
                               self.token.unwrap(),
                               self.interest,
-                              PollOpt::edge())
+                              PollOpt::edge() | PollOpt::oneshot())
     }
 }
 
@@ -108,17 +151,50 @@ impl AmqpAcceptor {
         self.conns[tok].token = Some(tok);
         event_loop.register_opt(&self.conns[tok].sock, tok, Interest::readable(), PollOpt::edge() | PollOpt::oneshot())
             .ok().expect("could not register socket with event loop");
-        Ok(())
+        // start the idle timer, even if the peer never says a word
+        self.conns[tok].update(event_loop)
     }
 
     fn conn_readable(&mut self, event_loop: &mut AmqpEventLoop, tok: Token) -> io::Result<()> {
         debug!("server conn readable; tok={:?}", tok);
-        self.conn(tok).readable(event_loop)
+        try!(self.conn(tok).readable(event_loop));
+        self.close_if_done(event_loop, tok)
     }
 
     fn conn_writable(&mut self, event_loop: &mut AmqpEventLoop, tok: Token) -> io::Result<()> {
         debug!("server conn writable; tok={:?}", tok);
-        self.conn(tok).writable(event_loop)
+        try!(self.conn(tok).writable(event_loop));
+        self.close_if_done(event_loop, tok)
+    }
+
+    fn conn_timeout(&mut self, event_loop: &mut AmqpEventLoop, tok: Token) -> io::Result<()> {
+        debug!("server conn timeout; tok={:?}", tok);
+        if !self.conns.contains(tok) {
+            // the connection went away in the meantime
+            return Ok(());
+        }
+
+        {
+            let conn = self.conn(tok);
+            conn.timeout = None;
+            try!(conn.update(event_loop));
+        }
+        self.close_if_done(event_loop, tok)
+    }
+
+    /// Drops the connection once its transport has nothing more to read
+    /// or write, e.g. after an idle timeout.
+    fn close_if_done(&mut self, event_loop: &mut AmqpEventLoop, tok: Token) -> io::Result<()> {
+        if !self.conn(tok).transport.closed() {
+            return Ok(());
+        }
+
+        debug!("server conn closed; tok={:?}", tok);
+        let conn = self.conns.remove(tok).unwrap();
+        if let Some(timeout) = conn.timeout {
+            event_loop.clear_timeout(timeout);
+        }
+        event_loop.deregister(&conn.sock)
     }
 
     fn conn<'a>(&'a mut self, tok: Token) -> &'a mut AmqpSocket {
@@ -162,7 +238,7 @@ impl AmqpHandler {
 
 
 impl Handler for AmqpHandler {
-    type Timeout = usize;
+    type Timeout = Token;
     type Message = ();
 
     fn writable(&mut self, event_loop: &mut AmqpEventLoop, token: Token) {
@@ -182,4 +258,8 @@ impl Handler for AmqpHandler {
             i => self.sock.conn_readable(event_loop, i).unwrap()
         };
     }
+
+    fn timeout(&mut self, event_loop: &mut AmqpEventLoop, token: Token) {
+        self.sock.conn_timeout(event_loop, token).unwrap();
+    }
 }
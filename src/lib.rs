@@ -5,17 +5,58 @@
 
 extern crate mio;
 extern crate libc;
-extern crate rustc_serialize;
+// re-exported for `amqp_described!`
+#[doc(hidden)] pub extern crate rustc_serialize;
 extern crate proton_sys;
+extern crate time;
 
 #[macro_use] extern crate log;
 
 pub use proton::{
-    Transport
+    Transport,
+    Head,
+    Tail,
+    Connection,
+    Session,
+    Link,
+    Sessions,
+    Links,
+    Unsettled,
+    SndSettleMode,
+    RcvSettleMode,
+    Sender,
+    Receiver,
+    Endpoint,
+    EndpointState,
+    State,
+    ANY_STATE,
+    Condition,
+    Message,
+    Delivery,
+    DeliveryState,
+    Outcome,
+    Tracker,
+    Terminus,
+    TerminusMut,
+    TerminusType,
+    Durability,
+    ExpiryPolicy,
+    DistributionMode,
+    AnnotationKey,
+    DataMap,
+    DataMapMut,
+    MapKey,
+    Error,
+    ErrorKind,
+    Value,
+    Described,
+    Descriptor,
+    types,
+    codec
 };
 
 mod io;
-mod proton;
+#[macro_use] mod proton;
 
 pub use io::{
     AmqpHandler
@@ -0,0 +1,103 @@
+use std::str;
+
+use super::super::value::Value;
+use super::*;
+use super::value_ref::ValueRef;
+
+/// Cursor over an encoded buffer.
+pub struct Parser<'a> {
+    pub bytes: &'a [u8],
+    pub pos: usize,
+}
+
+macro_rules! be_read {
+    ($bytes:expr, $t:ty) => ({
+        let mut v: u64 = 0;
+        for b in $bytes.iter() {
+            v = v << 8 | *b as u64;
+        }
+        v as $t
+    })
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(bytes: &'a [u8]) -> Parser<'a> {
+        Parser {bytes: bytes, pos: 0}
+    }
+
+    pub fn take(&mut self, n: usize) -> CodecResult<&'a [u8]> {
+        if self.bytes.len() - self.pos < n {
+            return Err(CodecError::UnexpectedEndOfData);
+        }
+
+        let bytes = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> CodecResult<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    pub fn u16(&mut self) -> CodecResult<u16> {
+        self.take(2).map(|b| be_read!(b, u16))
+    }
+
+    pub fn u32(&mut self) -> CodecResult<u32> {
+        self.take(4).map(|b| be_read!(b, u32))
+    }
+
+    pub fn u64(&mut self) -> CodecResult<u64> {
+        self.take(8).map(|b| be_read!(b, u64))
+    }
+
+    pub fn bytes16(&mut self) -> CodecResult<[u8; 16]> {
+        let bytes = try!(self.take(16));
+        let mut array = [0; 16];
+        for (dst, src) in array.iter_mut().zip(bytes.iter()) {
+            *dst = *src;
+        }
+        Ok(array)
+    }
+
+    /// Reads a size or count field, one byte wide for the 8 bit encodings.
+    pub fn size(&mut self, code: u8) -> CodecResult<usize> {
+        if code & 0x10 == 0 {
+            self.u8().map(|n| n as usize)
+        } else {
+            self.u32().map(|n| n as usize)
+        }
+    }
+
+    /// Reads the size and count of a compound value and returns the count
+    /// along with the bytes holding its contents.
+    pub fn compound(&mut self, code: u8) -> CodecResult<(usize, &'a [u8])> {
+        let size = try!(self.size(code));
+        if self.bytes.len() - self.pos < size {
+            return Err(CodecError::UnexpectedEndOfData);
+        }
+
+        let end = self.pos + size;
+        let count = try!(self.size(code));
+        if self.pos > end {
+            return Err(CodecError::InvalidSize);
+        }
+
+        let contents = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok((count, contents))
+    }
+
+    pub fn str(&mut self, code: u8) -> CodecResult<&'a str> {
+        let n = try!(self.size(code));
+        let bytes = try!(self.take(n));
+        str::from_utf8(bytes).map_err(|_| CodecError::InvalidUtf8)
+    }
+}
+
+/// Decodes the value at the start of `bytes`. Returns the value and the
+/// number of bytes it took, so several values can be read back to back.
+pub fn decode(bytes: &[u8]) -> CodecResult<(Value, usize)> {
+    let (value, n) = try!(ValueRef::parse(bytes));
+    Ok((try!(value.to_value()), n))
+}
@@ -0,0 +1,401 @@
+use std::{mem, ptr, slice};
+
+use super::super::value::Value;
+use super::*;
+
+/// Where the encoded bytes go.
+trait Sink {
+    fn put(&mut self, bytes: &[u8]) -> CodecResult<()>;
+}
+
+impl Sink for Vec<u8> {
+    fn put(&mut self, bytes: &[u8]) -> CodecResult<()> {
+        self.extend(bytes.iter().cloned());
+        Ok(())
+    }
+}
+
+struct SliceSink<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Sink for SliceSink<'a> {
+    fn put(&mut self, bytes: &[u8]) -> CodecResult<()> {
+        let end = self.pos + bytes.len();
+        if end > self.buf.len() {
+            return Err(CodecError::Overflow);
+        }
+
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), self.buf[self.pos..].as_mut_ptr(), bytes.len());
+        }
+        self.pos = end;
+        Ok(())
+    }
+}
+
+macro_rules! be_bytes {
+    ($v:expr, $n:expr) => ({
+        let v = $v as u64;
+        let mut bytes = [0u8; $n];
+        for i in 0..$n {
+            bytes[i] = (v >> (8 * ($n - 1 - i))) as u8;
+        }
+        bytes
+    })
+}
+
+fn fits_u8(n: usize) -> bool {
+    n <= 0xff
+}
+
+/// Constructor code and payload size of a value. They're worked out once,
+/// bottom-up, before anything is written, so the contents of nested
+/// compounds aren't sized again at every level.
+#[derive(Clone, Copy)]
+struct Layout {
+    code: u8,
+    // bytes after the constructor
+    size: usize,
+}
+
+/// Code of the most compact encoding for a value that isn't a compound.
+fn scalar_code(value: &Value) -> u8 {
+    match *value {
+        Value::Bool(true) => TRUE,
+        Value::Bool(false) => FALSE,
+        Value::Uint(0) => UINT0,
+        Value::Uint(v) if v <= 0xff => SMALLUINT,
+        Value::Ulong(0) => ULONG0,
+        Value::Ulong(v) if v <= 0xff => SMALLULONG,
+        Value::Int(v) if -128 <= v && v <= 127 => SMALLINT,
+        Value::Long(v) if -128 <= v && v <= 127 => SMALLLONG,
+        Value::Binary(ref v) if fits_u8(v.len()) => VBIN8,
+        Value::String(ref v) if fits_u8(v.len()) => STR8,
+        Value::Symbol(ref v) if fits_u8(v.len()) => SYM8,
+        _ => wide_code(value),
+    }
+}
+
+/// Code used for `value` as an array element, where all the elements share
+/// one constructor. Only the variable width types have a short form here,
+/// the fixed width ones always use the full width encoding.
+fn wide_code(value: &Value) -> u8 {
+    match *value {
+        Value::Null => NULL,
+        Value::Bool(_) => BOOLEAN,
+        Value::Ubyte(_) => UBYTE,
+        Value::Ushort(_) => USHORT,
+        Value::Uint(_) => UINT,
+        Value::Ulong(_) => ULONG,
+        Value::Byte(_) => BYTE,
+        Value::Short(_) => SHORT,
+        Value::Int(_) => INT,
+        Value::Long(_) => LONG,
+        Value::Float(_) => FLOAT,
+        Value::Double(_) => DOUBLE,
+        Value::Char(_) => CHAR,
+        Value::Timestamp(_) => TIMESTAMP,
+        Value::Decimal32(_) => DECIMAL32,
+        Value::Decimal64(_) => DECIMAL64,
+        Value::Decimal128(_) => DECIMAL128,
+        Value::Uuid(_) => UUID,
+        Value::Binary(_) => VBIN32,
+        Value::String(_) => STR32,
+        Value::Symbol(_) => SYM32,
+        Value::List(_) => LIST32,
+        Value::Map(_) => MAP32,
+        Value::Array(_) => ARRAY32,
+        Value::Described(_, _) => DESCRIBED,
+    }
+}
+
+/// Strips the descriptor off described array elements.
+fn undescribed(value: &Value) -> &Value {
+    match *value {
+        Value::Described(_, ref value) => value,
+        ref value => value
+    }
+}
+
+fn descriptor(value: &Value) -> Option<&Value> {
+    match *value {
+        Value::Described(ref descriptor, _) => Some(descriptor),
+        _ => None
+    }
+}
+
+/// Code shared by the elements of an array. They must all be of the same
+/// type, other than null, and in a described array have the same
+/// descriptor.
+fn element_code(values: &[Value]) -> CodecResult<u8> {
+    let first = match values.first() {
+        Some(first) => first,
+        None => return Ok(NULL)
+    };
+
+    let code = wide_code(undescribed(first));
+    if code == NULL {
+        // nulls take no bytes, so the count would be all there is
+        return Err(CodecError::InvalidArray);
+    }
+    let mut short = true;
+    for value in values.iter() {
+        if descriptor(value) != descriptor(first) || wide_code(undescribed(value)) != code {
+            return Err(CodecError::InvalidArray);
+        }
+
+        short = short && match *undescribed(value) {
+            Value::Binary(ref v) => fits_u8(v.len()),
+            Value::String(ref v) | Value::Symbol(ref v) => fits_u8(v.len()),
+            _ => false
+        };
+    }
+
+    Ok(match (code, short) {
+        (VBIN32, true) => VBIN8,
+        (STR32, true) => STR8,
+        (SYM32, true) => SYM8,
+        (code, _) => code
+    })
+}
+
+/// Width of the size and count fields of a compound, and of the size of a
+/// binary, string or symbol.
+fn width(code: u8) -> usize {
+    match code {
+        VBIN8 | STR8 | SYM8 | LIST8 | MAP8 | ARRAY8 => 1,
+        _ => 4
+    }
+}
+
+/// Payload size of a value that isn't a compound, encoded as `code`.
+fn scalar_size(value: &Value, code: u8) -> usize {
+    match code {
+        NULL | TRUE | FALSE | UINT0 | ULONG0 => 0,
+        BOOLEAN | UBYTE | BYTE | SMALLUINT | SMALLULONG | SMALLINT | SMALLLONG => 1,
+        USHORT | SHORT => 2,
+        UINT | INT | FLOAT | CHAR | DECIMAL32 => 4,
+        ULONG | LONG | DOUBLE | TIMESTAMP | DECIMAL64 => 8,
+        DECIMAL128 | UUID => 16,
+        _ => width(code) + match *value {
+            Value::Binary(ref v) => v.len(),
+            Value::String(ref v) | Value::Symbol(ref v) => v.len(),
+            _ => unreachable!()
+        }
+    }
+}
+
+/// Works out the layout of `value` and of everything in it, and appends
+/// them to `layouts` in the order they're written out. `element` is the
+/// code shared by the elements of the array `value` is in, if it's in one.
+fn layout(value: &Value, element: Option<u8>, layouts: &mut Vec<Layout>) -> CodecResult<Layout> {
+    let idx = layouts.len();
+    layouts.push(Layout {code: NULL, size: 0});
+
+    let (compact, content) = match *value {
+        Value::List(ref values) => {
+            let mut content = 0;
+            for value in values.iter() {
+                content += 1 + try!(layout(value, None, layouts)).size;
+            }
+
+            let code = if values.is_empty() {
+                LIST0
+            } else if fits_u8(values.len()) && fits_u8(content + 1) {
+                LIST8
+            } else {
+                LIST32
+            };
+            (code, content)
+        },
+        Value::Map(ref entries) => {
+            let mut content = 0;
+            for &(ref key, ref value) in entries.iter() {
+                content += 1 + try!(layout(key, None, layouts)).size;
+                content += 1 + try!(layout(value, None, layouts)).size;
+            }
+
+            let code = if fits_u8(2 * entries.len()) && fits_u8(content + 1) {MAP8} else {MAP32};
+            (code, content)
+        },
+        Value::Array(ref values) => {
+            let code = try!(element_code(values));
+            // the element constructor, with the descriptor of a described array
+            let mut content = 1;
+            if let Some(descriptor) = values.first().and_then(descriptor) {
+                content += 2 + try!(layout(descriptor, None, layouts)).size;
+            }
+            for value in values.iter() {
+                content += try!(layout(undescribed(value), Some(code), layouts)).size;
+            }
+
+            let code = if fits_u8(values.len()) && fits_u8(content + 1) {ARRAY8} else {ARRAY32};
+            (code, content)
+        },
+        Value::Described(ref descriptor, ref value) => {
+            let content = 2 + try!(layout(descriptor, None, layouts)).size
+                + try!(layout(value, None, layouts)).size;
+            (DESCRIBED, content)
+        },
+        _ => {
+            let code = element.unwrap_or_else(|| scalar_code(value));
+            layouts[idx] = Layout {code: code, size: scalar_size(value, code)};
+            return Ok(layouts[idx]);
+        }
+    };
+
+    let code = element.unwrap_or(compact);
+    let size = match code {
+        LIST0 => 0,
+        DESCRIBED => content,
+        // size and count
+        code => 2 * width(code) + content
+    };
+    layouts[idx] = Layout {code: code, size: size};
+    Ok(layouts[idx])
+}
+
+/// Lays out `value` and returns the layouts with its encoded size.
+fn plan(value: &Value) -> CodecResult<(Vec<Layout>, usize)> {
+    let mut layouts = Vec::new();
+    let size = 1 + try!(layout(value, None, &mut layouts)).size;
+    Ok((layouts, size))
+}
+
+/// Size of `value` once encoded.
+pub fn encoded_size(value: &Value) -> CodecResult<usize> {
+    plan(value).map(|(_, size)| size)
+}
+
+fn put_size<S: Sink>(sink: &mut S, code: u8, n: usize) -> CodecResult<()> {
+    match width(code) {
+        1 => sink.put(&[n as u8]),
+        _ => sink.put(&be_bytes!(n, 4)),
+    }
+}
+
+/// Puts the size and the count of a compound. The size counts the bytes
+/// of the count and of the contents.
+fn put_compound_size<S: Sink>(sink: &mut S, layout: Layout, count: usize) -> CodecResult<()> {
+    try!(put_size(sink, layout.code, layout.size - width(layout.code)));
+    put_size(sink, layout.code, count)
+}
+
+fn put_payload<S: Sink>(sink: &mut S, value: &Value, layout: Layout,
+                        layouts: &mut slice::Iter<Layout>) -> CodecResult<()> {
+    let code = layout.code;
+    match *value {
+        Value::Null => Ok(()),
+        Value::Bool(v) => if code == BOOLEAN {sink.put(&[v as u8])} else {Ok(())},
+        Value::Ubyte(v) => sink.put(&[v]),
+        Value::Ushort(v) => sink.put(&be_bytes!(v, 2)),
+        Value::Uint(v) => match code {
+            UINT0 => Ok(()),
+            SMALLUINT => sink.put(&[v as u8]),
+            _ => sink.put(&be_bytes!(v, 4)),
+        },
+        Value::Ulong(v) => match code {
+            ULONG0 => Ok(()),
+            SMALLULONG => sink.put(&[v as u8]),
+            _ => sink.put(&be_bytes!(v, 8)),
+        },
+        Value::Byte(v) => sink.put(&[v as u8]),
+        Value::Short(v) => sink.put(&be_bytes!(v as u16, 2)),
+        Value::Int(v) => match code {
+            SMALLINT => sink.put(&[v as u8]),
+            _ => sink.put(&be_bytes!(v as u32, 4)),
+        },
+        Value::Long(v) => match code {
+            SMALLLONG => sink.put(&[v as u8]),
+            _ => sink.put(&be_bytes!(v, 8)),
+        },
+        Value::Float(v) => sink.put(&be_bytes!(unsafe{mem::transmute::<f32, u32>(v)}, 4)),
+        Value::Double(v) => sink.put(&be_bytes!(unsafe{mem::transmute::<f64, u64>(v)}, 8)),
+        Value::Char(v) => sink.put(&be_bytes!(v as u32, 4)),
+        Value::Timestamp(v) => sink.put(&be_bytes!(v, 8)),
+        Value::Decimal32(v) => sink.put(&be_bytes!(v, 4)),
+        Value::Decimal64(v) => sink.put(&be_bytes!(v, 8)),
+        Value::Decimal128(ref v) | Value::Uuid(ref v) => sink.put(v),
+        Value::Binary(ref v) => {
+            try!(put_size(sink, code, v.len()));
+            sink.put(v)
+        },
+        Value::String(ref v) | Value::Symbol(ref v) => {
+            try!(put_size(sink, code, v.len()));
+            sink.put(v.as_bytes())
+        },
+        Value::List(ref values) => {
+            if code == LIST0 {
+                return Ok(());
+            }
+
+            try!(put_compound_size(sink, layout, values.len()));
+            for value in values.iter() {
+                try!(put_value(sink, value, layouts));
+            }
+            Ok(())
+        },
+        Value::Map(ref entries) => {
+            try!(put_compound_size(sink, layout, 2 * entries.len()));
+            for &(ref key, ref value) in entries.iter() {
+                try!(put_value(sink, key, layouts));
+                try!(put_value(sink, value, layouts));
+            }
+            Ok(())
+        },
+        Value::Array(ref values) => {
+            try!(put_compound_size(sink, layout, values.len()));
+            if let Some(descriptor) = values.first().and_then(descriptor) {
+                try!(sink.put(&[DESCRIBED]));
+                try!(put_value(sink, descriptor, layouts));
+            }
+
+            // the elements are laid out with the code they share
+            let element_code = if values.is_empty() {
+                NULL
+            } else {
+                layouts.clone().next().unwrap().code
+            };
+            try!(sink.put(&[element_code]));
+            for value in values.iter() {
+                let layout = *layouts.next().unwrap();
+                try!(put_payload(sink, undescribed(value), layout, layouts));
+            }
+            Ok(())
+        },
+        Value::Described(ref descriptor, ref value) => {
+            try!(put_value(sink, descriptor, layouts));
+            put_value(sink, value, layouts)
+        },
+    }
+}
+
+fn put_value<S: Sink>(sink: &mut S, value: &Value, layouts: &mut slice::Iter<Layout>) -> CodecResult<()> {
+    let layout = *layouts.next().unwrap();
+    try!(sink.put(&[layout.code]));
+    put_payload(sink, value, layout, layouts)
+}
+
+/// Appends the encoding of `value` to `out`. Fails with
+/// `CodecError::InvalidArray` for an array whose elements don't share
+/// their type or descriptor.
+pub fn encode(value: &Value, out: &mut Vec<u8>) -> CodecResult<()> {
+    let (layouts, size) = try!(plan(value));
+    out.reserve(size);
+    put_value(out, value, &mut layouts.iter())
+}
+
+/// Encodes `value` at the start of `out` and returns the number of bytes
+/// written, or `CodecError::Overflow` when it doesn't fit.
+pub fn encode_slice(value: &Value, out: &mut [u8]) -> CodecResult<usize> {
+    let (layouts, size) = try!(plan(value));
+    if size > out.len() {
+        return Err(CodecError::Overflow);
+    }
+
+    let mut sink = SliceSink {buf: out, pos: 0};
+    try!(put_value(&mut sink, value, &mut layouts.iter()));
+    Ok(sink.pos)
+}
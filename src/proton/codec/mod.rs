@@ -0,0 +1,314 @@
+//! Native AMQP 1.0 codec.
+//!
+//! Encodes and decodes `Value`s straight to and from bytes without going
+//! through a `pn_data_t`. The encoder always picks the most compact
+//! encoding for a value: `uint0`/`ulong0` for zero, the `small*` forms for
+//! values that fit in a byte, `str8`/`sym8`/`vbin8` for short strings and
+//! `list0`/`list8` for short lists. The output can be read by proton and
+//! anything it writes can be read back here.
+//!
+//! `decode` copies everything into a `Value`. `ValueRef::parse` reads a
+//! value in place instead, handing out slices of the input for strings and
+//! binaries and only parsing compound values as they're iterated.
+
+use std::fmt;
+use std::error;
+
+use super::value::Value;
+
+pub use self::encode::{encode, encode_slice, encoded_size};
+pub use self::decode::decode;
+pub use self::value_ref::{ValueRef, ListRef, MapRef, ArrayRef, DescribedRef, Iter, MapIter};
+
+mod encode;
+mod decode;
+mod value_ref;
+
+// format codes, see section 1.6 of the AMQP 1.0 spec
+const DESCRIBED: u8 = 0x00;
+const NULL: u8 = 0x40;
+const TRUE: u8 = 0x41;
+const FALSE: u8 = 0x42;
+const UINT0: u8 = 0x43;
+const ULONG0: u8 = 0x44;
+const LIST0: u8 = 0x45;
+const BOOLEAN: u8 = 0x56;
+const UBYTE: u8 = 0x50;
+const BYTE: u8 = 0x51;
+const SMALLUINT: u8 = 0x52;
+const SMALLULONG: u8 = 0x53;
+const SMALLINT: u8 = 0x54;
+const SMALLLONG: u8 = 0x55;
+const USHORT: u8 = 0x60;
+const SHORT: u8 = 0x61;
+const UINT: u8 = 0x70;
+const INT: u8 = 0x71;
+const FLOAT: u8 = 0x72;
+const CHAR: u8 = 0x73;
+const DECIMAL32: u8 = 0x74;
+const ULONG: u8 = 0x80;
+const LONG: u8 = 0x81;
+const DOUBLE: u8 = 0x82;
+const TIMESTAMP: u8 = 0x83;
+const DECIMAL64: u8 = 0x84;
+const DECIMAL128: u8 = 0x94;
+const UUID: u8 = 0x98;
+const VBIN8: u8 = 0xa0;
+const STR8: u8 = 0xa1;
+const SYM8: u8 = 0xa3;
+const VBIN32: u8 = 0xb0;
+const STR32: u8 = 0xb1;
+const SYM32: u8 = 0xb3;
+const LIST8: u8 = 0xc0;
+const MAP8: u8 = 0xc1;
+const LIST32: u8 = 0xd0;
+const MAP32: u8 = 0xd1;
+const ARRAY8: u8 = 0xe0;
+const ARRAY32: u8 = 0xf0;
+
+/// How deep values may be nested in compound and described values when
+/// decoding, so crafted input can't exhaust the stack.
+pub const MAX_DEPTH: usize = 100;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CodecError {
+    /// the input ends in the middle of a value
+    UnexpectedEndOfData,
+    /// the output buffer is too small for the value
+    Overflow,
+    /// unknown or reserved format code
+    InvalidCode(u8),
+    /// a compound's size or count doesn't match its contents
+    InvalidSize,
+    InvalidUtf8,
+    InvalidChar(u32),
+    /// array elements of different types or with different descriptors, or
+    /// a non-empty array of elements taking no bytes, such as nulls
+    InvalidArray,
+    /// values nested deeper than `MAX_DEPTH`
+    TooDeep,
+}
+
+pub type CodecResult<T> = Result<T, CodecError>;
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CodecError::UnexpectedEndOfData => write!(f, "unexpected end of data"),
+            CodecError::Overflow => write!(f, "output buffer too small"),
+            CodecError::InvalidCode(code) => write!(f, "invalid format code 0x{:02x}", code),
+            CodecError::InvalidSize => write!(f, "size does not match the contents"),
+            CodecError::InvalidUtf8 => write!(f, "invalid utf-8"),
+            CodecError::InvalidChar(c) => write!(f, "invalid char 0x{:x}", c),
+            CodecError::InvalidArray => write!(f, "array elements differ in type or descriptor, or take no bytes"),
+            CodecError::TooDeep => write!(f, "values nested more than {} deep", MAX_DEPTH),
+        }
+    }
+}
+
+impl error::Error for CodecError {
+    fn description(&self) -> &str {
+        "AMQP codec error"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::value::Value;
+    use super::super::encoder;
+    use proton_sys;
+
+    fn sample() -> Vec<Value> {
+        let long_string: String = (0..300).map(|_| 'x').collect();
+        let many: Vec<Value> = (0..300).map(|i| Value::Uint(i)).collect();
+
+        vec![
+            Value::Null,
+            Value::Bool(true), Value::Bool(false),
+            Value::Ubyte(255), Value::Ushort(65535),
+            Value::Uint(0), Value::Uint(200), Value::Uint(70000),
+            Value::Ulong(0), Value::Ulong(200), Value::Ulong(1 << 40),
+            Value::Byte(-1), Value::Short(-300),
+            Value::Int(-1), Value::Int(-70000),
+            Value::Long(127), Value::Long(-1 << 40),
+            Value::Float(1.5), Value::Double(-0.25),
+            Value::Char('é'),
+            Value::Timestamp(1436000000000),
+            Value::Decimal32(0x2200000a), Value::Decimal64(0x223c00000000000a),
+            Value::Decimal128([9; 16]), Value::Uuid([0xab; 16]),
+            Value::Binary(vec![0, 1, 2]), Value::Binary(vec![7; 300]),
+            Value::String("hello".to_string()), Value::String(long_string.clone()),
+            Value::Symbol("amqp:accepted:list".to_string()), Value::Symbol(long_string),
+            Value::List(vec![]),
+            Value::List(vec![Value::Int(1), Value::String("two".to_string()), Value::Null]),
+            Value::List(many.clone()),
+            Value::Map(vec![(Value::Symbol("x-opt-a".to_string()), Value::Long(1)),
+                            (Value::Ulong(2), Value::List(vec![]))]),
+            Value::Array(vec![]),
+            Value::Array(vec![Value::Int(1), Value::Int(-1)]),
+            Value::Array(vec![Value::Symbol("a".to_string()), Value::Symbol("bc".to_string())]),
+            Value::Array(many),
+            Value::Array(vec![Value::List(vec![Value::Bool(true)]), Value::List(vec![])]),
+            Value::Array(vec![
+                Value::Described(Box::new(Value::Ulong(0x24)), Box::new(Value::Uint(1))),
+                Value::Described(Box::new(Value::Ulong(0x24)), Box::new(Value::Uint(2))),
+            ]),
+            Value::Described(Box::new(Value::Ulong(0x70)),
+                             Box::new(Value::List(vec![Value::Bool(true), Value::Ubyte(4)]))),
+        ]
+    }
+
+    /// Decodes `bytes` with proton.
+    fn pn_decode(bytes: &[u8]) -> Value {
+        unsafe {
+            let data = proton_sys::pn_data(16);
+            let n = proton_sys::pn_data_decode(data,
+                                               bytes.as_ptr() as *const ::libc::c_char,
+                                               bytes.len() as proton_sys::size_t);
+            assert_eq!(bytes.len() as proton_sys::ssize_t, n);
+            proton_sys::pn_data_rewind(data);
+            proton_sys::pn_data_next(data);
+            let value = Value::get(data).unwrap();
+            proton_sys::pn_data_free(data);
+            value
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for value in sample() {
+            let mut bytes = Vec::new();
+            encode(&value, &mut bytes).unwrap();
+            assert_eq!(bytes.len(), encoded_size(&value).unwrap());
+            assert_eq!((value, bytes.len()), decode(&bytes).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_proton_reads_our_encoding() {
+        for value in sample() {
+            let mut bytes = Vec::new();
+            encode(&value, &mut bytes).unwrap();
+            assert_eq!(value, pn_decode(&bytes));
+        }
+    }
+
+    #[test]
+    fn test_we_read_proton_encoding() {
+        for value in sample() {
+            let bytes = encoder::encode(&value).unwrap();
+            assert_eq!((value, bytes.len()), decode(&bytes).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_same_bytes_as_proton() {
+        // values for which proton picks the same encoding we do
+        let values = vec![
+            Value::Null, Value::Ubyte(3), Value::Uint(200), Value::Uint(70000),
+            Value::Ulong(200), Value::Ulong(1 << 40), Value::Int(-1), Value::Long(-1 << 40),
+            Value::Double(-0.25), Value::String("hello".to_string()),
+            Value::Symbol("amqp:accepted:list".to_string()), Value::Binary(vec![0, 1, 2]),
+        ];
+
+        for value in values {
+            let mut bytes = Vec::new();
+            encode(&value, &mut bytes).unwrap();
+            assert_eq!(encoder::encode(&value).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_compact_encodings() {
+        let cases = vec![
+            (Value::Uint(0), vec![0x43]),
+            (Value::Ulong(0), vec![0x44]),
+            (Value::Ulong(0x70), vec![0x53, 0x70]),
+            (Value::Bool(true), vec![0x41]),
+            (Value::Long(-2), vec![0x55, 0xfe]),
+            (Value::String("ab".to_string()), vec![0xa1, 2, b'a', b'b']),
+            (Value::List(vec![]), vec![0x45]),
+            (Value::List(vec![Value::Null, Value::Uint(1)]), vec![0xc0, 4, 2, 0x40, 0x52, 1]),
+            (Value::Map(vec![(Value::Symbol("k".to_string()), Value::Null)]),
+             vec![0xc1, 5, 2, 0xa3, 1, b'k', 0x40]),
+            (Value::Array(vec![Value::Int(1), Value::Int(2)]),
+             vec![0xe0, 10, 2, 0x71, 0, 0, 0, 1, 0, 0, 0, 2]),
+            (Value::Described(Box::new(Value::Ulong(0x24)), Box::new(Value::List(vec![]))),
+             vec![0x00, 0x53, 0x24, 0x45]),
+        ];
+
+        for (value, expected) in cases {
+            let mut bytes = Vec::new();
+            encode(&value, &mut bytes).unwrap();
+            assert_eq!(expected, bytes);
+        }
+    }
+
+    #[test]
+    fn test_wide_encodings() {
+        let long_string: String = (0..256).map(|_| 'x').collect();
+        let mut bytes = Vec::new();
+        encode(&Value::String(long_string), &mut bytes).unwrap();
+        assert_eq!(&[0xb1, 0, 0, 1, 0][..], &bytes[..5]);
+
+        let mut bytes = Vec::new();
+        encode(&Value::List((0..300).map(|_| Value::Null).collect()), &mut bytes).unwrap();
+        assert_eq!(&[0xd0, 0, 0, 1, 0x30, 0, 0, 1, 0x2c][..], &bytes[..9]);
+    }
+
+    #[test]
+    fn test_deeply_nested_encoding() {
+        let mut value = Value::Null;
+        for _ in 0..64 {
+            value = Value::List(vec![Value::Map(vec![(Value::Null, value)])]);
+        }
+        let mut bytes = Vec::new();
+        encode(&value, &mut bytes).unwrap();
+        assert_eq!(bytes.len(), encoded_size(&value).unwrap());
+    }
+
+    #[test]
+    fn test_mixed_arrays() {
+        let mut bytes = Vec::new();
+        let mixed = Value::Array(vec![Value::Int(1), Value::Long(2)]);
+        assert_eq!(Err(CodecError::InvalidArray), encode(&mixed, &mut bytes));
+
+        let descriptors = Value::Array(vec![
+            Value::Described(Box::new(Value::Ulong(0x24)), Box::new(Value::Uint(1))),
+            Value::Described(Box::new(Value::Ulong(0x25)), Box::new(Value::Uint(2))),
+        ]);
+        assert_eq!(Err(CodecError::InvalidArray), encode(&descriptors, &mut bytes));
+
+        // the count of these couldn't be trusted when decoding
+        let nulls = Value::Array(vec![Value::Null, Value::Null]);
+        assert_eq!(Err(CodecError::InvalidArray), encode(&nulls, &mut bytes));
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn test_encode_slice() {
+        let value = Value::List(vec![Value::String("hello".to_string()), Value::Ulong(1 << 40)]);
+        let mut buf = [0; 64];
+        let n = encode_slice(&value, &mut buf).unwrap();
+        assert_eq!(encoded_size(&value).unwrap(), n);
+        assert_eq!((value.clone(), n), decode(&buf[..n]).unwrap());
+
+        let mut small = [0; 8];
+        assert_eq!(Err(CodecError::Overflow), encode_slice(&value, &mut small));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(Err(CodecError::UnexpectedEndOfData), decode(&[]));
+        assert_eq!(Err(CodecError::UnexpectedEndOfData), decode(&[0xa1, 5, b'a']));
+        assert_eq!(Err(CodecError::InvalidCode(0xff)), decode(&[0xff]));
+        assert_eq!(Err(CodecError::InvalidUtf8), decode(&[0xa1, 1, 0xff]));
+        assert_eq!(Err(CodecError::InvalidSize), decode(&[0xc0, 1, 2, 0x40, 0x40]));
+    }
+
+    #[test]
+    fn test_decode_leaves_trailing_bytes() {
+        assert_eq!(Ok((Value::Uint(0), 1)), decode(&[0x43, 0x43]));
+    }
+}
@@ -0,0 +1,594 @@
+use std::{char, cmp, mem};
+
+use super::super::value::Value;
+use super::*;
+use super::decode::Parser;
+
+/// An AMQP value borrowed from an encoded buffer.
+///
+/// Strings, symbols and binaries point into the buffer, and compound values
+/// are only parsed as far as their header: their contents are read when
+/// iterated. Reading a couple of entries out of a large map doesn't decode
+/// the rest of it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ValueRef<'a> {
+    Null,
+    Bool(bool),
+    Ubyte(u8),
+    Ushort(u16),
+    Uint(u32),
+    Ulong(u64),
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Char(char),
+    Timestamp(i64),
+    Decimal32(u32),
+    Decimal64(u64),
+    Decimal128([u8; 16]),
+    Uuid([u8; 16]),
+    Binary(&'a [u8]),
+    String(&'a str),
+    Symbol(&'a str),
+    List(ListRef<'a>),
+    Map(MapRef<'a>),
+    Array(ArrayRef<'a>),
+    Described(DescribedRef<'a>),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ListRef<'a> {
+    count: usize,
+    contents: &'a [u8],
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MapRef<'a> {
+    count: usize,
+    contents: &'a [u8],
+}
+
+/// An array. Its elements share a single constructor, which is kept in
+/// `code`, and described arrays have their descriptor in front of the
+/// elements rather than on each of them.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ArrayRef<'a> {
+    count: usize,
+    code: u8,
+    descriptor: Option<&'a [u8]>,
+    contents: &'a [u8],
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DescribedRef<'a> {
+    descriptor: &'a [u8],
+    value: &'a [u8],
+}
+
+/// Iterator over the elements of a list or an array.
+pub struct Iter<'a> {
+    parser: Parser<'a>,
+    // element constructor for arrays
+    code: Option<u8>,
+    remaining: usize,
+}
+
+/// Iterator over the entries of a map.
+pub struct MapIter<'a> {
+    iter: Iter<'a>,
+}
+
+impl<'a> Parser<'a> {
+    /// Reads a value, skipping over the contents of compound values.
+    fn value_ref(&mut self) -> CodecResult<ValueRef<'a>> {
+        self.described_ref(0)
+    }
+
+    /// Reads a value found inside `depth` descriptors.
+    fn described_ref(&mut self, depth: usize) -> CodecResult<ValueRef<'a>> {
+        let code = try!(self.u8());
+        if code != DESCRIBED {
+            return self.payload_ref(code, depth);
+        }
+        if depth >= MAX_DEPTH {
+            return Err(CodecError::TooDeep);
+        }
+
+        let start = self.pos;
+        try!(self.described_ref(depth + 1));
+        let middle = self.pos;
+        try!(self.described_ref(depth + 1));
+
+        Ok(ValueRef::Described(DescribedRef {
+            descriptor: &self.bytes[start..middle],
+            value: &self.bytes[middle..self.pos],
+        }))
+    }
+
+    /// Reads a value encoded as `code`, its constructor having been read.
+    /// `depth` counts the descriptors it's in, array ones included.
+    fn payload_ref(&mut self, code: u8, depth: usize) -> CodecResult<ValueRef<'a>> {
+        Ok(match code {
+            NULL => ValueRef::Null,
+            TRUE => ValueRef::Bool(true),
+            FALSE => ValueRef::Bool(false),
+            BOOLEAN => ValueRef::Bool(try!(self.u8()) != 0),
+            UBYTE => ValueRef::Ubyte(try!(self.u8())),
+            USHORT => ValueRef::Ushort(try!(self.u16())),
+            UINT0 => ValueRef::Uint(0),
+            SMALLUINT => ValueRef::Uint(try!(self.u8()) as u32),
+            UINT => ValueRef::Uint(try!(self.u32())),
+            ULONG0 => ValueRef::Ulong(0),
+            SMALLULONG => ValueRef::Ulong(try!(self.u8()) as u64),
+            ULONG => ValueRef::Ulong(try!(self.u64())),
+            BYTE => ValueRef::Byte(try!(self.u8()) as i8),
+            SHORT => ValueRef::Short(try!(self.u16()) as i16),
+            SMALLINT => ValueRef::Int(try!(self.u8()) as i8 as i32),
+            INT => ValueRef::Int(try!(self.u32()) as i32),
+            SMALLLONG => ValueRef::Long(try!(self.u8()) as i8 as i64),
+            LONG => ValueRef::Long(try!(self.u64()) as i64),
+            FLOAT => ValueRef::Float(unsafe{mem::transmute::<u32, f32>(try!(self.u32()))}),
+            DOUBLE => ValueRef::Double(unsafe{mem::transmute::<u64, f64>(try!(self.u64()))}),
+            CHAR => {
+                let c = try!(self.u32());
+                match char::from_u32(c) {
+                    Some(c) => ValueRef::Char(c),
+                    None => return Err(CodecError::InvalidChar(c))
+                }
+            },
+            TIMESTAMP => ValueRef::Timestamp(try!(self.u64()) as i64),
+            DECIMAL32 => ValueRef::Decimal32(try!(self.u32())),
+            DECIMAL64 => ValueRef::Decimal64(try!(self.u64())),
+            DECIMAL128 => ValueRef::Decimal128(try!(self.bytes16())),
+            UUID => ValueRef::Uuid(try!(self.bytes16())),
+            VBIN8 | VBIN32 => {
+                let n = try!(self.size(code));
+                ValueRef::Binary(try!(self.take(n)))
+            },
+            STR8 | STR32 => ValueRef::String(try!(self.str(code))),
+            SYM8 | SYM32 => ValueRef::Symbol(try!(self.str(code))),
+            LIST0 => ValueRef::List(ListRef {count: 0, contents: &[]}),
+            LIST8 | LIST32 => {
+                let (count, contents) = try!(self.compound(code));
+                // every element takes at least a byte
+                if count > contents.len() {
+                    return Err(CodecError::InvalidSize);
+                }
+                ValueRef::List(ListRef {count: count, contents: contents})
+            },
+            MAP8 | MAP32 => {
+                let (count, contents) = try!(self.compound(code));
+                if count % 2 != 0 || count > contents.len() {
+                    return Err(CodecError::InvalidSize);
+                }
+                ValueRef::Map(MapRef {count: count / 2, contents: contents})
+            },
+            ARRAY8 | ARRAY32 => {
+                let (count, contents) = try!(self.compound(code));
+                let mut header = Parser::new(contents);
+
+                let mut element_code = try!(header.u8());
+                let descriptor = if element_code == DESCRIBED {
+                    if depth >= MAX_DEPTH {
+                        return Err(CodecError::TooDeep);
+                    }
+                    try!(header.described_ref(depth + 1));
+                    let descriptor = &contents[1..header.pos];
+                    element_code = try!(header.u8());
+                    Some(descriptor)
+                } else {
+                    None
+                };
+
+                // every element takes at least a byte, except with the
+                // constructors that are values themselves, where nothing
+                // would bound how many elements the count claims
+                match element_code {
+                    NULL | TRUE | FALSE | UINT0 | ULONG0 | LIST0 if count > 0 => {
+                        return Err(CodecError::InvalidArray);
+                    },
+                    _ if count > contents.len() - header.pos => {
+                        return Err(CodecError::InvalidSize);
+                    },
+                    _ => {}
+                }
+
+                ValueRef::Array(ArrayRef {
+                    count: count,
+                    code: element_code,
+                    descriptor: descriptor,
+                    contents: &contents[header.pos..],
+                })
+            },
+            _ => return Err(CodecError::InvalidCode(code))
+        })
+    }
+}
+
+impl<'a> ValueRef<'a> {
+    /// Parses the value at the start of `bytes`. Returns the value and the
+    /// number of bytes it took.
+    pub fn parse(bytes: &'a [u8]) -> CodecResult<(ValueRef<'a>, usize)> {
+        let mut parser = Parser::new(bytes);
+        let value = try!(parser.value_ref());
+        Ok((value, parser.pos))
+    }
+
+    /// The contents of a string or a symbol.
+    pub fn as_str(&self) -> Option<&'a str> {
+        match *self {
+            ValueRef::String(s) | ValueRef::Symbol(s) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match *self {
+            ValueRef::Binary(b) => Some(b),
+            _ => None
+        }
+    }
+
+    /// Decodes the whole value, copying it out of the buffer.
+    pub fn to_value(&self) -> CodecResult<Value> {
+        self.to_value_at(0)
+    }
+
+    /// Decodes the value found inside `depth` compound or described values.
+    fn to_value_at(&self, depth: usize) -> CodecResult<Value> {
+        if depth > MAX_DEPTH {
+            return Err(CodecError::TooDeep);
+        }
+
+        Ok(match *self {
+            ValueRef::Null => Value::Null,
+            ValueRef::Bool(v) => Value::Bool(v),
+            ValueRef::Ubyte(v) => Value::Ubyte(v),
+            ValueRef::Ushort(v) => Value::Ushort(v),
+            ValueRef::Uint(v) => Value::Uint(v),
+            ValueRef::Ulong(v) => Value::Ulong(v),
+            ValueRef::Byte(v) => Value::Byte(v),
+            ValueRef::Short(v) => Value::Short(v),
+            ValueRef::Int(v) => Value::Int(v),
+            ValueRef::Long(v) => Value::Long(v),
+            ValueRef::Float(v) => Value::Float(v),
+            ValueRef::Double(v) => Value::Double(v),
+            ValueRef::Char(v) => Value::Char(v),
+            ValueRef::Timestamp(v) => Value::Timestamp(v),
+            ValueRef::Decimal32(v) => Value::Decimal32(v),
+            ValueRef::Decimal64(v) => Value::Decimal64(v),
+            ValueRef::Decimal128(v) => Value::Decimal128(v),
+            ValueRef::Uuid(v) => Value::Uuid(v),
+            ValueRef::Binary(v) => Value::Binary(v.to_vec()),
+            ValueRef::String(v) => Value::String(v.to_string()),
+            ValueRef::Symbol(v) => Value::Symbol(v.to_string()),
+            ValueRef::List(ref list) => Value::List(try!(to_values(list.iter(), depth + 1))),
+            ValueRef::Map(ref map) => {
+                let mut entries = Vec::with_capacity(cmp::min(map.len(), map.contents.len()));
+                for entry in map.iter() {
+                    let (key, value) = try!(entry);
+                    entries.push((try!(key.to_value_at(depth + 1)), try!(value.to_value_at(depth + 1))));
+                }
+                Value::Map(entries)
+            },
+            ValueRef::Array(ref array) => {
+                let values = try!(to_values(array.iter(), depth + 1));
+                match array.descriptor() {
+                    Some(descriptor) => {
+                        let descriptor = try!(try!(descriptor).to_value_at(depth + 1));
+                        Value::Array(values.into_iter().map(|value| {
+                            Value::Described(Box::new(descriptor.clone()), Box::new(value))
+                        }).collect())
+                    },
+                    None => Value::Array(values)
+                }
+            },
+            ValueRef::Described(ref described) => {
+                let descriptor = try!(try!(described.descriptor()).to_value_at(depth + 1));
+                let value = try!(try!(described.value()).to_value_at(depth + 1));
+                Value::Described(Box::new(descriptor), Box::new(value))
+            },
+        })
+    }
+}
+
+fn to_values(iter: Iter, depth: usize) -> CodecResult<Vec<Value>> {
+    // the count comes off the wire, the contents are what's really there
+    let mut values = Vec::with_capacity(cmp::min(iter.remaining, iter.parser.bytes.len()));
+    for value in iter {
+        values.push(try!(try!(value).to_value_at(depth)));
+    }
+    Ok(values)
+}
+
+fn parse_whole(bytes: &[u8]) -> CodecResult<ValueRef> {
+    let (value, n) = try!(ValueRef::parse(bytes));
+    if n != bytes.len() {
+        return Err(CodecError::InvalidSize);
+    }
+    Ok(value)
+}
+
+impl<'a> ListRef<'a> {
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn iter(&self) -> Iter<'a> {
+        Iter {parser: Parser::new(self.contents), code: None, remaining: self.count}
+    }
+
+    /// The element at `idx`, parsing only as far as that element.
+    pub fn get(&self, idx: usize) -> CodecResult<Option<ValueRef<'a>>> {
+        match self.iter().nth(idx) {
+            Some(value) => value.map(Some),
+            None => Ok(None)
+        }
+    }
+}
+
+impl<'a> MapRef<'a> {
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn iter(&self) -> MapIter<'a> {
+        MapIter {iter: Iter {parser: Parser::new(self.contents), code: None, remaining: 2 * self.count}}
+    }
+
+    /// The value of the first entry whose key is the string or symbol `key`.
+    /// Entries after it are not parsed.
+    pub fn get(&self, key: &str) -> CodecResult<Option<ValueRef<'a>>> {
+        for entry in self.iter() {
+            let (k, v) = try!(entry);
+            if k.as_str() == Some(key) {
+                return Ok(Some(v));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'a> ArrayRef<'a> {
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The descriptor shared by the elements of a described array.
+    pub fn descriptor(&self) -> Option<CodecResult<ValueRef<'a>>> {
+        self.descriptor.map(parse_whole)
+    }
+
+    /// Iterates over the elements, without their descriptor in the case of
+    /// a described array.
+    pub fn iter(&self) -> Iter<'a> {
+        Iter {parser: Parser::new(self.contents), code: Some(self.code), remaining: self.count}
+    }
+}
+
+impl<'a> DescribedRef<'a> {
+    pub fn descriptor(&self) -> CodecResult<ValueRef<'a>> {
+        parse_whole(self.descriptor)
+    }
+
+    pub fn value(&self) -> CodecResult<ValueRef<'a>> {
+        parse_whole(self.value)
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = CodecResult<ValueRef<'a>>;
+
+    fn next(&mut self) -> Option<CodecResult<ValueRef<'a>>> {
+        if self.remaining == 0 {
+            // the contents have to hold exactly `count` elements
+            if self.parser.pos != self.parser.bytes.len() {
+                self.parser.pos = self.parser.bytes.len();
+                return Some(Err(CodecError::InvalidSize));
+            }
+            return None;
+        }
+
+        self.remaining -= 1;
+        let result = match self.code {
+            Some(code) => self.parser.payload_ref(code, 0),
+            None => self.parser.value_ref()
+        };
+
+        match result {
+            Ok(value) => Some(Ok(value)),
+            Err(e) => {
+                self.remaining = 0;
+                self.parser.pos = self.parser.bytes.len();
+                // the compound's size was checked against the buffer, so
+                // running out means the size is wrong rather than the
+                // buffer being short
+                Some(Err(match e {
+                    CodecError::UnexpectedEndOfData => CodecError::InvalidSize,
+                    e => e
+                }))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining + 1))
+    }
+}
+
+impl<'a> Iterator for MapIter<'a> {
+    type Item = CodecResult<(ValueRef<'a>, ValueRef<'a>)>;
+
+    fn next(&mut self) -> Option<CodecResult<(ValueRef<'a>, ValueRef<'a>)>> {
+        let key = match self.iter.next() {
+            Some(Ok(key)) => key,
+            Some(Err(e)) => return Some(Err(e)),
+            None => return None
+        };
+
+        match self.iter.next() {
+            Some(Ok(value)) => Some(Ok((key, value))),
+            Some(Err(e)) => Some(Err(e)),
+            None => Some(Err(CodecError::InvalidSize))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{encode, CodecError, MAX_DEPTH};
+    use super::super::super::value::Value;
+
+    fn properties() -> Vec<u8> {
+        let value = Value::Map(vec![
+            (Value::String("region".to_string()), Value::String("eu-west".to_string())),
+            (Value::String("payload".to_string()), Value::Binary(vec![1, 2, 3])),
+            (Value::Symbol("priority".to_string()), Value::Ubyte(4)),
+            (Value::String("tags".to_string()), Value::Array(vec![
+                Value::Symbol("a".to_string()), Value::Symbol("b".to_string())])),
+        ]);
+        let mut bytes = Vec::new();
+        encode(&value, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_borrows_from_buffer() {
+        let bytes = properties();
+        let map = match ValueRef::parse(&bytes).unwrap() {
+            (ValueRef::Map(map), n) => {
+                assert_eq!(bytes.len(), n);
+                map
+            },
+            v => panic!("expected a map, got {:?}", v)
+        };
+
+        assert_eq!(4, map.len());
+        let region = map.get("region").unwrap().unwrap().as_str().unwrap();
+        assert_eq!("eu-west", region);
+        let start = bytes.as_ptr() as usize;
+        assert!(region.as_ptr() as usize > start && (region.as_ptr() as usize) < start + bytes.len());
+
+        assert_eq!(Some(&[1, 2, 3][..]), map.get("payload").unwrap().unwrap().as_bytes());
+        assert_eq!(Some(ValueRef::Ubyte(4)), map.get("priority").unwrap());
+        assert_eq!(None, map.get("missing").unwrap());
+    }
+
+    #[test]
+    fn test_array_iteration() {
+        let bytes = properties();
+        let (value, _) = ValueRef::parse(&bytes).unwrap();
+        let tags = match value {
+            ValueRef::Map(map) => map.get("tags").unwrap().unwrap(),
+            _ => unreachable!()
+        };
+
+        match tags {
+            ValueRef::Array(array) => {
+                let tags: Vec<&str> = array.iter().map(|v| v.unwrap().as_str().unwrap()).collect();
+                assert_eq!(vec!["a", "b"], tags);
+            },
+            v => panic!("expected an array, got {:?}", v)
+        }
+    }
+
+    #[test]
+    fn test_to_value() {
+        let value = Value::List(vec![
+            Value::Described(Box::new(Value::Ulong(0x73)), Box::new(Value::List(vec![Value::Null]))),
+            Value::Array(vec![
+                Value::Described(Box::new(Value::Symbol("d".to_string())), Box::new(Value::Int(1))),
+                Value::Described(Box::new(Value::Symbol("d".to_string())), Box::new(Value::Int(2))),
+            ]),
+            Value::Map(vec![(Value::Null, Value::Bool(false))]),
+        ]);
+        let mut bytes = Vec::new();
+        encode(&value, &mut bytes).unwrap();
+
+        let (value_ref, _) = ValueRef::parse(&bytes).unwrap();
+        assert_eq!(value, value_ref.to_value().unwrap());
+    }
+
+    #[test]
+    fn test_lazy_parsing() {
+        // the second element is garbage, the first can still be read
+        let bytes = [0xc0, 4, 2, 0x52, 7, 0xff];
+        let list = match ValueRef::parse(&bytes).unwrap() {
+            (ValueRef::List(list), _) => list,
+            v => panic!("expected a list, got {:?}", v)
+        };
+
+        assert_eq!(Some(ValueRef::Uint(7)), list.get(0).unwrap());
+        assert_eq!(Err(CodecError::InvalidCode(0xff)), list.get(1));
+        assert!(ValueRef::parse(&bytes).unwrap().0.to_value().is_err());
+    }
+
+    #[test]
+    fn test_hostile_counts() {
+        // a list claiming u32::MAX elements in 4 bytes
+        let bytes = [0xd0, 0, 0, 0, 4, 0xff, 0xff, 0xff, 0xff];
+        assert_eq!(Err(CodecError::InvalidSize), ValueRef::parse(&bytes));
+
+        // an array of u32::MAX ints without any
+        let bytes = [0xf0, 0, 0, 0, 5, 0xff, 0xff, 0xff, 0xff, 0x71];
+        assert_eq!(Err(CodecError::InvalidSize), ValueRef::parse(&bytes));
+
+        // or of elements that take no bytes at all
+        for &code in [0x40, 0x41, 0x42, 0x43, 0x44, 0x45].iter() {
+            let bytes = [0xf0, 0, 0, 0, 5, 0xff, 0xff, 0xff, 0xff, code];
+            assert_eq!(Err(CodecError::InvalidArray), ValueRef::parse(&bytes));
+        }
+
+        // an empty one is fine
+        let (array, _) = ValueRef::parse(&[0xe0, 2, 0, 0x40]).unwrap();
+        assert_eq!(Value::Array(vec![]), array.to_value().unwrap());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let descriptors = vec![0u8; 100000];
+        assert_eq!(Err(CodecError::TooDeep), ValueRef::parse(&descriptors));
+
+        // empty arrays whose descriptor is an array whose descriptor is ...
+        let mut arrays = vec![0x40];
+        for _ in 0..MAX_DEPTH + 1 {
+            let size = arrays.len() + 6;
+            let mut outer = vec![0xf0, (size >> 24) as u8, (size >> 16) as u8, (size >> 8) as u8, size as u8,
+                                 0, 0, 0, 0, 0x00];
+            outer.extend(arrays.into_iter());
+            outer.push(0x40);
+            arrays = outer;
+        }
+        assert_eq!(Err(CodecError::TooDeep), ValueRef::parse(&arrays));
+
+        let mut value = Value::Null;
+        for _ in 0..MAX_DEPTH {
+            value = Value::List(vec![value]);
+        }
+        let mut bytes = Vec::new();
+        encode(&value, &mut bytes).unwrap();
+        assert_eq!(value, ValueRef::parse(&bytes).unwrap().0.to_value().unwrap());
+
+        let value = Value::List(vec![value]);
+        let mut bytes = Vec::new();
+        encode(&value, &mut bytes).unwrap();
+        assert_eq!(Err(CodecError::TooDeep), ValueRef::parse(&bytes).unwrap().0.to_value());
+    }
+}
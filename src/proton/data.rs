@@ -0,0 +1,360 @@
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::vec;
+use proton_sys;
+
+use super::decoder::{DecodeResult, DecoderError};
+use super::types::Symbol;
+use super::value::Value;
+
+/// Types allowed as keys of a map section.
+pub trait MapKey: Sized + PartialEq {
+    fn to_value(&self) -> Value;
+
+    /// Returns `None` for keys of a type this map doesn't allow.
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+/// Application properties are keyed by string.
+impl MapKey for String {
+    fn to_value(&self) -> Value {
+        Value::String(self.clone())
+    }
+
+    fn from_value(value: &Value) -> Option<String> {
+        match *value {
+            Value::String(ref s) => Some(s.clone()),
+            _ => None
+        }
+    }
+}
+
+/// Connection and link properties are keyed by symbol.
+impl MapKey for Symbol {
+    fn to_value(&self) -> Value {
+        Value::Symbol(self.0.clone())
+    }
+
+    fn from_value(value: &Value) -> Option<Symbol> {
+        match *value {
+            Value::Symbol(ref s) => Some(Symbol(s.clone())),
+            _ => None
+        }
+    }
+}
+
+/// Key of the message and delivery annotations: a symbol, or a ulong
+/// reserved for use by the spec.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum AnnotationKey {
+    Symbol(String),
+    Ulong(u64),
+}
+
+impl MapKey for AnnotationKey {
+    fn to_value(&self) -> Value {
+        match *self {
+            AnnotationKey::Symbol(ref s) => Value::Symbol(s.clone()),
+            AnnotationKey::Ulong(n) => Value::Ulong(n),
+        }
+    }
+
+    fn from_value(value: &Value) -> Option<AnnotationKey> {
+        match *value {
+            Value::Symbol(ref s) => Some(AnnotationKey::Symbol(s.clone())),
+            Value::Ulong(n) => Some(AnnotationKey::Ulong(n)),
+            _ => None
+        }
+    }
+}
+
+impl<'a> From<&'a str> for AnnotationKey {
+    fn from(s: &'a str) -> AnnotationKey {
+        AnnotationKey::Symbol(s.to_string())
+    }
+}
+
+impl From<Symbol> for AnnotationKey {
+    fn from(s: Symbol) -> AnnotationKey {
+        AnnotationKey::Symbol(s.0)
+    }
+}
+
+impl From<u64> for AnnotationKey {
+    fn from(n: u64) -> AnnotationKey {
+        AnnotationKey::Ulong(n)
+    }
+}
+
+/// Read-only view of a map held by a message, such as its application
+/// properties. Entries whose key isn't a `K`, or that can't be decoded, are
+/// skipped.
+pub struct DataMap<'a, K> {
+    data: *mut proton_sys::pn_data_t,
+    __phantom: PhantomData<(&'a (), K)>
+}
+
+/// Mutable view of a map held by a message. Entries the view skips are
+/// kept as they are when it's changed.
+pub struct DataMapMut<'a, K> {
+    map: DataMap<'a, K>
+}
+
+impl<'a, K: MapKey> DataMap<'a, K> {
+    /// Unsafe because the data must outlive `'a`.
+    pub unsafe fn new(data: *mut proton_sys::pn_data_t) -> DataMap<'a, K> {
+        DataMap {data: data, __phantom: PhantomData}
+    }
+
+    /// Calls `f` with the key of each entry, or `None` if it isn't a `K`,
+    /// while the cursor is on the entry's value. Only the keys are decoded.
+    /// Stops when `f` returns false.
+    fn walk<F>(&self, mut f: F) where F: FnMut(Option<K>) -> bool {
+        let data = self.data;
+        unsafe {
+            proton_sys::pn_data_rewind(data);
+            if proton_sys::pn_data_next(data) == 0 || proton_sys::pn_data_type(data) != proton_sys::PN_MAP {
+                return;
+            }
+
+            proton_sys::pn_data_enter(data);
+            while proton_sys::pn_data_next(data) != 0 {
+                let key = Value::get(data).ok().and_then(|k| K::from_value(&k));
+                if proton_sys::pn_data_next(data) == 0 || !f(key) {
+                    break;
+                }
+            }
+            proton_sys::pn_data_exit(data);
+        }
+    }
+
+    /// Every entry, whatever the type of its key. Fails rather than
+    /// leaving out entries that can't be decoded.
+    fn raw_entries(&self) -> DecodeResult<Vec<(Value, Value)>> {
+        unsafe {
+            proton_sys::pn_data_rewind(self.data);
+            if proton_sys::pn_data_next(self.data) == 0 {
+                return Ok(Vec::new());
+            }
+        }
+
+        match try!(Value::get(self.data)) {
+            Value::Map(entries) => Ok(entries),
+            value => Err(DecoderError::ExpectedError("PN_MAP".to_string(), format!("{:?}", value)))
+        }
+    }
+
+    pub fn get<Q: Into<K>>(&self, key: Q) -> Option<Value> {
+        let key = key.into();
+        let data = self.data;
+        let mut value = None;
+        self.walk(|k| {
+            if k.as_ref() != Some(&key) {
+                return true;
+            }
+            value = Value::get(data).ok();
+            false
+        });
+        value
+    }
+
+    pub fn contains_key<Q: Into<K>>(&self, key: Q) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        let mut len = 0;
+        self.walk(|k| {
+            if k.is_some() {
+                len += 1;
+            }
+            true
+        });
+        len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over a copy of the entries, in the order they're encoded.
+    pub fn iter(&self) -> vec::IntoIter<(K, Value)> {
+        let data = self.data;
+        let mut entries = Vec::new();
+        self.walk(|k| {
+            if let (Some(k), Ok(v)) = (k, Value::get(data)) {
+                entries.push((k, v));
+            }
+            true
+        });
+        entries.into_iter()
+    }
+}
+
+impl<'a, K: MapKey> DataMapMut<'a, K> {
+    /// Unsafe because the data must outlive `'a` and nothing else may use
+    /// it meanwhile.
+    pub unsafe fn new(data: *mut proton_sys::pn_data_t) -> DataMapMut<'a, K> {
+        DataMapMut {map: DataMap::new(data)}
+    }
+
+    fn set_entries(&mut self, entries: Vec<(Value, Value)>) -> DecodeResult<()> {
+        if entries.is_empty() {
+            // leave the section out altogether
+            Value::set_data(self.map.data, None)
+        } else {
+            Value::set_data(self.map.data, Some(&Value::Map(entries)))
+        }
+    }
+
+    /// Sets `key` to `value` and returns the previous value. A new key is
+    /// added after the existing ones.
+    ///
+    /// The map is written out again, so this fails, leaving it untouched,
+    /// if any of its entries can't be decoded, or `value` isn't valid (see
+    /// `Value::validate`).
+    pub fn insert<Q: Into<K>>(&mut self, key: Q, value: Value) -> DecodeResult<Option<Value>> {
+        let key = key.into().to_value();
+        let mut entries = try!(self.map.raw_entries());
+
+        let old = match entries.iter_mut().find(|&&mut (ref k, _)| *k == key) {
+            Some(entry) => Some(::std::mem::replace(&mut entry.1, value.clone())),
+            None => None
+        };
+
+        if old.is_none() {
+            entries.push((key, value));
+        }
+        try!(self.set_entries(entries));
+        Ok(old)
+    }
+
+    /// Removes `key` and returns its value. Fails like `insert`.
+    pub fn remove<Q: Into<K>>(&mut self, key: Q) -> DecodeResult<Option<Value>> {
+        let key = key.into().to_value();
+        let mut entries = try!(self.map.raw_entries());
+
+        match entries.iter().position(|&(ref k, _)| *k == key) {
+            Some(idx) => {
+                let (_, value) = entries.remove(idx);
+                try!(self.set_entries(entries));
+                Ok(Some(value))
+            },
+            None => Ok(None)
+        }
+    }
+
+    pub fn clear(&mut self) {
+        unsafe {proton_sys::pn_data_clear(self.map.data)};
+    }
+}
+
+impl<'a, K> Deref for DataMapMut<'a, K> {
+    type Target = DataMap<'a, K>;
+
+    fn deref(&self) -> &DataMap<'a, K> {
+        &self.map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::encoder::pn_bytes;
+    use super::super::value::Value;
+    use proton_sys;
+
+    #[test]
+    fn test_insert_get_remove() {
+        let data = unsafe{proton_sys::pn_data(16)};
+        {
+            let mut map: DataMapMut<String> = unsafe {DataMapMut::new(data)};
+            assert!(map.is_empty());
+            assert_eq!(Ok(None), map.insert("region", Value::String("eu".to_string())));
+            assert_eq!(Ok(None), map.insert("hops", Value::Int(1)));
+            assert_eq!(Ok(Some(Value::Int(1))), map.insert("hops", Value::Int(2)));
+
+            assert_eq!(2, map.len());
+            assert_eq!(Some(Value::Int(2)), map.get("hops"));
+            assert_eq!(vec![("region".to_string(), Value::String("eu".to_string())),
+                            ("hops".to_string(), Value::Int(2))],
+                       map.iter().collect::<Vec<_>>());
+
+            assert_eq!(Ok(Some(Value::String("eu".to_string()))), map.remove("region"));
+            assert_eq!(Ok(None), map.remove("region"));
+            assert!(!map.contains_key("region"));
+        }
+        unsafe {proton_sys::pn_data_free(data)};
+    }
+
+    #[test]
+    fn test_annotation_keys() {
+        let data = unsafe{proton_sys::pn_data(16)};
+        {
+            let mut map: DataMapMut<AnnotationKey> = unsafe {DataMapMut::new(data)};
+            map.insert("x-opt-partition", Value::Int(3)).unwrap();
+            map.insert(0x1234u64, Value::Null).unwrap();
+            assert_eq!(Some(Value::Int(3)), map.get("x-opt-partition"));
+            assert_eq!(Some(Value::Null), map.get(0x1234u64));
+        }
+
+        // the key went in as a symbol
+        unsafe {
+            proton_sys::pn_data_rewind(data);
+            proton_sys::pn_data_next(data);
+        }
+        match Value::get(data).unwrap() {
+            Value::Map(entries) => {
+                assert_eq!(Value::Symbol("x-opt-partition".to_string()), entries[0].0);
+                assert_eq!(Value::Ulong(0x1234), entries[1].0);
+            },
+            v => panic!("expected a map, got {:?}", v)
+        }
+        unsafe {proton_sys::pn_data_free(data)};
+    }
+
+    #[test]
+    fn test_skips_invalid_keys() {
+        let data = unsafe{proton_sys::pn_data(16)};
+        Value::Map(vec![(Value::Int(1), Value::Null),
+                        (Value::String("ok".to_string()), Value::Bool(true))]).put(data).unwrap();
+
+        let mut map: DataMapMut<String> = unsafe {DataMapMut::new(data)};
+        assert_eq!(1, map.len());
+        assert_eq!(Some(Value::Bool(true)), map.get("ok"));
+
+        // but they're kept when the map changes
+        map.insert("new", Value::Null).unwrap();
+        assert_eq!(2, map.len());
+        assert_eq!(Some(Value::Map(vec![(Value::Int(1), Value::Null),
+                                        (Value::String("ok".to_string()), Value::Bool(true)),
+                                        (Value::String("new".to_string()), Value::Null)])),
+                   Value::from_data(data).unwrap());
+        unsafe {proton_sys::pn_data_free(data)};
+    }
+
+    #[test]
+    fn test_keeps_undecodable_entries() {
+        let data = unsafe{proton_sys::pn_data(16)};
+        unsafe {
+            proton_sys::pn_data_put_map(data);
+            proton_sys::pn_data_enter(data);
+            Value::String("bad".to_string()).put(data).unwrap();
+            proton_sys::pn_data_put_string(data, pn_bytes(&[0xff]));
+            Value::String("ok".to_string()).put(data).unwrap();
+            Value::Bool(true).put(data).unwrap();
+            proton_sys::pn_data_exit(data);
+        }
+
+        let mut map: DataMapMut<String> = unsafe {DataMapMut::new(data)};
+        assert_eq!(Some(Value::Bool(true)), map.get("ok"));
+        assert_eq!(None, map.get("bad"));
+        assert!(map.insert("new", Value::Null).is_err());
+        assert!(map.remove("ok").is_err());
+
+        // nothing was written
+        assert_eq!(Some(Value::Bool(true)), map.get("ok"));
+        assert_eq!(None, map.get("new"));
+        unsafe {proton_sys::pn_data_free(data)};
+    }
+}
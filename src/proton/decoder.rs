@@ -0,0 +1,768 @@
+use std::string;
+use std::{char, slice, str};
+use std::ffi::CStr;
+use rustc_serialize as serialize;
+use std::marker::PhantomData;
+use proton_sys;
+
+use super::encoder::{ErrorCode, ParserError, AMQP_TYPE, variant_for_type};
+use super::value::Value;
+
+/*
+Unmarshal walks AMQP data held in a `pn_data_t` and decodes it into a Rust
+value. It is the counterpart of `encoder::Encoder` and accepts the same type
+mappings, with a few widening rules so data produced by other AMQP peers can
+be read too:
+
+ +-------------------------------------+--------------------------------------------+
+ |AMQP type                            |Rust type                                   |
+ +-------------------------------------+--------------------------------------------+
+ |ubyte, ushort, uint, ulong           |any unsigned integer the value fits in      |
+ +-------------------------------------+--------------------------------------------+
+ |byte, short, int, long               |any signed integer the value fits in        |
+ +-------------------------------------+--------------------------------------------+
+ |float, double                        |f64 (f32 only reads float)                  |
+ +-------------------------------------+--------------------------------------------+
+ |string, symbol                       |String                                      |
+ +-------------------------------------+--------------------------------------------+
+ |null                                 |(), Option::None                            |
+ +-------------------------------------+--------------------------------------------+
+ |list, array                          |sequences, tuples and structs               |
+ +-------------------------------------+--------------------------------------------+
+ |map                                  |maps                                        |
+ +-------------------------------------+--------------------------------------------+
+
+Enums are read either from a string holding the variant name or from a list
+whose first element is the variant name followed by the variant arguments.
+Variants of the AMQP_TYPE enum are chosen by the AMQP type of the value
+instead, which is how `Value` and the wrappers in `types` read timestamps,
+decimals, uuids and described values. Binaries also read as `Vec<u8>`.
+*/
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum DecoderError {
+    ParseError(ParserError),
+    ExpectedError(string::String, string::String),
+    MissingFieldError(string::String),
+    UnknownVariantError(string::String),
+    ApplicationError(string::String)
+}
+
+pub type DecodeResult<T> = Result<T, DecoderError>;
+
+fn syntax_error(code: ErrorCode) -> DecoderError {
+    DecoderError::ParseError(ParserError::SyntaxError(code, 0, 0))
+}
+
+fn type_name(t: proton_sys::pn_type_t) -> string::String {
+    unsafe {
+        let name = proton_sys::pn_type_name(t);
+        if name.is_null() {
+            return format!("{}", t);
+        }
+        CStr::from_ptr(name).to_string_lossy().into_owned()
+    }
+}
+
+fn expected(expected: &str, found: proton_sys::pn_type_t) -> DecoderError {
+    DecoderError::ExpectedError(expected.to_string(), type_name(found))
+}
+
+/// Returns the bytes a `pn_bytes_t` points to. The slice is only valid
+/// as long as the `pn_data_t` it was read from is not modified.
+pub unsafe fn bytes_as_slice<'a>(bytes: proton_sys::pn_bytes_t) -> &'a [u8] {
+    if bytes.size == 0 || bytes.start.is_null() {
+        &[]
+    } else {
+        slice::from_raw_parts(bytes.start as *const u8, bytes.size as usize)
+    }
+}
+
+macro_rules! narrow {
+    ($v:expr, $wide:ty => $t:ty) => ({
+        let v: $wide = $v;
+        if (v as $t) as $wide != v {
+            return Err(DecoderError::ExpectedError(stringify!($t).to_string(),
+                                                   v.to_string()));
+        }
+        v as $t
+    })
+}
+
+pub struct Decoder<'d> {
+    data: *mut proton_sys::pn_data_t,
+    owned: bool,
+    // set by read_enum when the enum is AMQP_TYPE
+    typed: bool,
+    // AMQP type of the AMQP_TYPE variant being read
+    hint: Option<proton_sys::pn_type_t>,
+    // bytes of the binary, uuid or decimal128 being read as a sequence
+    bytes: Option<(Vec<u8>, usize)>,
+    // descriptor of the described array whose elements are being read
+    descriptor: Option<*mut proton_sys::pn_data_t>,
+    // descriptor of the array element being read as a Described variant
+    element_descriptor: Option<*mut proton_sys::pn_data_t>,
+    __phantom: PhantomData<&'d ()>
+}
+
+impl<'d> Decoder<'d> {
+    /// Decodes a single AMQP value from `bytes` and returns a Decoder
+    /// positioned before it.
+    pub fn new(bytes: &[u8]) -> DecodeResult<Decoder<'d>> {
+        let data = unsafe{proton_sys::pn_data(16)};
+        let decoder = Decoder::with_data(data, true);
+
+        let consumed = unsafe {
+            proton_sys::pn_data_decode(data,
+                                       bytes.as_ptr() as *const ::libc::c_char,
+                                       bytes.len() as proton_sys::size_t)
+        };
+
+        if consumed == proton_sys::PN_UNDERFLOW as proton_sys::ssize_t {
+            return Err(syntax_error(ErrorCode::UnexpectedEndOfData));
+        } else if consumed < 0 {
+            return Err(syntax_error(ErrorCode::InvalidData));
+        } else if (consumed as usize) < bytes.len() {
+            return Err(DecoderError::ParseError(
+                ParserError::SyntaxError(ErrorCode::TrailingBytes, consumed as usize, 0)));
+        }
+
+        unsafe {proton_sys::pn_data_rewind(data)};
+        Ok(decoder)
+    }
+
+    /// Reads from an existing `pn_data_t`, starting at its first value.
+    /// The data is not freed when the Decoder goes away.
+    pub fn from_data(data: *mut proton_sys::pn_data_t) -> Decoder<'d> {
+        unsafe {proton_sys::pn_data_rewind(data)};
+        Decoder::with_data(data, false)
+    }
+
+    fn with_data(data: *mut proton_sys::pn_data_t, owned: bool) -> Decoder<'d> {
+        Decoder {
+            data: data,
+            owned: owned,
+            typed: false,
+            hint: None,
+            bytes: None,
+            descriptor: None,
+            element_descriptor: None,
+            __phantom: PhantomData
+        }
+    }
+
+    /// Moves to the next value and returns its type.
+    fn next(&mut self) -> DecodeResult<proton_sys::pn_type_t> {
+        unsafe {
+            if proton_sys::pn_data_next(self.data) == 0 {
+                return Err(syntax_error(ErrorCode::UnexpectedEndOfData));
+            }
+            Ok(proton_sys::pn_data_type(self.data))
+        }
+    }
+
+    /// Returns the type of the next value without moving to it.
+    fn peek(&mut self) -> Option<proton_sys::pn_type_t> {
+        unsafe {
+            let point = proton_sys::pn_data_point(self.data);
+            let next = if proton_sys::pn_data_next(self.data) != 0 {
+                Some(proton_sys::pn_data_type(self.data))
+            } else {
+                None
+            };
+            proton_sys::pn_data_restore(self.data, point);
+            next
+        }
+    }
+
+    fn read_unsigned(&mut self, name: &str) -> DecodeResult<u64> {
+        let t = try!(self.next());
+        let hint = self.hint.take();
+        unsafe {
+            match t {
+                proton_sys::PN_UBYTE => Ok(proton_sys::pn_data_get_ubyte(self.data) as u64),
+                proton_sys::PN_USHORT => Ok(proton_sys::pn_data_get_ushort(self.data) as u64),
+                proton_sys::PN_UINT => Ok(proton_sys::pn_data_get_uint(self.data) as u64),
+                proton_sys::PN_ULONG => Ok(proton_sys::pn_data_get_ulong(self.data)),
+                proton_sys::PN_DECIMAL32 if hint == Some(t) => {
+                    Ok(proton_sys::pn_data_get_decimal32(self.data) as u64)
+                },
+                proton_sys::PN_DECIMAL64 if hint == Some(t) => {
+                    Ok(proton_sys::pn_data_get_decimal64(self.data))
+                },
+                _ => Err(expected(name, t))
+            }
+        }
+    }
+
+    fn read_signed(&mut self, name: &str) -> DecodeResult<i64> {
+        let t = try!(self.next());
+        let hint = self.hint.take();
+        unsafe {
+            match t {
+                proton_sys::PN_BYTE => Ok(proton_sys::pn_data_get_byte(self.data) as i64),
+                proton_sys::PN_SHORT => Ok(proton_sys::pn_data_get_short(self.data) as i64),
+                proton_sys::PN_INT => Ok(proton_sys::pn_data_get_int(self.data) as i64),
+                proton_sys::PN_LONG => Ok(proton_sys::pn_data_get_long(self.data)),
+                proton_sys::PN_TIMESTAMP if hint == Some(t) => {
+                    Ok(proton_sys::pn_data_get_timestamp(self.data))
+                },
+                _ => Err(expected(name, t))
+            }
+        }
+    }
+
+    /// Runs `f` with `bytes` as the elements of the sequence being read.
+    fn read_bytes<T, F>(&mut self, bytes: Vec<u8>, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>, usize) -> DecodeResult<T>,
+    {
+        let len = bytes.len();
+        let outer = self.bytes.take();
+        self.bytes = Some((bytes, 0));
+        let result = f(self, len);
+        self.bytes = outer;
+        result
+    }
+
+    /// Reads a variant of the AMQP_TYPE enum, picking it by the AMQP type
+    /// of the next value.
+    fn read_typed<T, F>(&mut self, names: &[&str], mut f: F) -> DecodeResult<T> where
+        F: FnMut(&mut Decoder<'d>, usize) -> DecodeResult<T>,
+    {
+        if let Some(descriptor) = self.descriptor {
+            if let Some(idx) = names.iter().position(|n| *n == "Described") {
+                // elements of a described array read as described values,
+                // all with the descriptor of the array
+                self.descriptor = None;
+                self.element_descriptor = Some(descriptor);
+                let result = f(self, idx);
+                self.element_descriptor = None;
+                self.descriptor = Some(descriptor);
+                return result;
+            }
+        }
+
+        let t = match self.peek() {
+            Some(t) => t,
+            None => return Err(syntax_error(ErrorCode::UnexpectedEndOfData))
+        };
+
+        let idx = match variant_for_type(t).and_then(|n| names.iter().position(|v| *v == n)) {
+            Some(idx) => idx,
+            None => return Err(DecoderError::ExpectedError(names.join(" or "), type_name(t)))
+        };
+
+        match t {
+            proton_sys::PN_NULL => {
+                try!(self.next());
+                f(self, idx)
+            },
+            proton_sys::PN_DESCRIBED => {
+                // the descriptor and the value are the variant arguments
+                try!(self.next());
+                self.nested(|d| f(d, idx))
+            },
+            _ => {
+                self.hint = Some(t);
+                let result = f(self, idx);
+                self.hint = None;
+                result
+            }
+        }
+    }
+
+    /// Enters the compound value the cursor is on, runs `f` and
+    /// leaves it again so the cursor ends up on the compound itself.
+    fn nested<T, F>(&mut self, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>) -> DecodeResult<T>,
+    {
+        let outer = self.descriptor.take();
+        unsafe {proton_sys::pn_data_enter(self.data)};
+        let result = f(self);
+        unsafe {proton_sys::pn_data_exit(self.data)};
+        self.descriptor = outer;
+        result
+    }
+}
+
+impl<'d> Drop for Decoder<'d> {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe {proton_sys::pn_data_free(self.data)};
+        }
+    }
+}
+
+impl<'d> serialize::Decoder for Decoder<'d> {
+    type Error = DecoderError;
+
+    fn read_nil(&mut self) -> DecodeResult<()> {
+        match try!(self.next()) {
+            proton_sys::PN_NULL => Ok(()),
+            t => Err(expected("PN_NULL", t))
+        }
+    }
+
+    fn read_usize(&mut self) -> DecodeResult<usize> {
+        Ok(narrow!(try!(self.read_unsigned("usize")), u64 => usize))
+    }
+    fn read_u64(&mut self) -> DecodeResult<u64> {
+        self.read_unsigned("PN_ULONG")
+    }
+    fn read_u32(&mut self) -> DecodeResult<u32> {
+        Ok(narrow!(try!(self.read_unsigned("PN_UINT")), u64 => u32))
+    }
+    fn read_u16(&mut self) -> DecodeResult<u16> {
+        Ok(narrow!(try!(self.read_unsigned("PN_USHORT")), u64 => u16))
+    }
+    fn read_u8(&mut self) -> DecodeResult<u8> {
+        if let Some((ref bytes, ref mut pos)) = self.bytes {
+            if *pos >= bytes.len() {
+                return Err(syntax_error(ErrorCode::UnexpectedEndOfData));
+            }
+            *pos += 1;
+            return Ok(bytes[*pos - 1]);
+        }
+        Ok(narrow!(try!(self.read_unsigned("PN_UBYTE")), u64 => u8))
+    }
+
+    fn read_isize(&mut self) -> DecodeResult<isize> {
+        Ok(narrow!(try!(self.read_signed("isize")), i64 => isize))
+    }
+    fn read_i64(&mut self) -> DecodeResult<i64> {
+        self.read_signed("PN_LONG")
+    }
+    fn read_i32(&mut self) -> DecodeResult<i32> {
+        Ok(narrow!(try!(self.read_signed("PN_INT")), i64 => i32))
+    }
+    fn read_i16(&mut self) -> DecodeResult<i16> {
+        Ok(narrow!(try!(self.read_signed("PN_SHORT")), i64 => i16))
+    }
+    fn read_i8(&mut self) -> DecodeResult<i8> {
+        Ok(narrow!(try!(self.read_signed("PN_BYTE")), i64 => i8))
+    }
+
+    fn read_bool(&mut self) -> DecodeResult<bool> {
+        match try!(self.next()) {
+            proton_sys::PN_BOOL => Ok(unsafe{proton_sys::pn_data_get_bool(self.data) != 0}),
+            t => Err(expected("PN_BOOL", t))
+        }
+    }
+
+    fn read_f64(&mut self) -> DecodeResult<f64> {
+        match try!(self.next()) {
+            proton_sys::PN_DOUBLE => Ok(unsafe{proton_sys::pn_data_get_double(self.data)}),
+            proton_sys::PN_FLOAT => Ok(unsafe{proton_sys::pn_data_get_float(self.data) as f64}),
+            t => Err(expected("PN_DOUBLE", t))
+        }
+    }
+    fn read_f32(&mut self) -> DecodeResult<f32> {
+        match try!(self.next()) {
+            proton_sys::PN_FLOAT => Ok(unsafe{proton_sys::pn_data_get_float(self.data)}),
+            t => Err(expected("PN_FLOAT", t))
+        }
+    }
+
+    fn read_char(&mut self) -> DecodeResult<char> {
+        match try!(self.next()) {
+            proton_sys::PN_CHAR => {
+                let c = unsafe{proton_sys::pn_data_get_char(self.data)};
+                char::from_u32(c).ok_or(
+                    DecoderError::ExpectedError("char".to_string(), c.to_string()))
+            },
+            t => Err(expected("PN_CHAR", t))
+        }
+    }
+
+    fn read_str(&mut self) -> DecodeResult<string::String> {
+        self.hint = None;
+        let bytes = match try!(self.next()) {
+            proton_sys::PN_STRING => unsafe{proton_sys::pn_data_get_string(self.data)},
+            proton_sys::PN_SYMBOL => unsafe{proton_sys::pn_data_get_symbol(self.data)},
+            t => return Err(expected("PN_STRING", t))
+        };
+
+        match str::from_utf8(unsafe{bytes_as_slice(bytes)}) {
+            Ok(s) => Ok(s.to_string()),
+            Err(_) => Err(syntax_error(ErrorCode::InvalidData))
+        }
+    }
+
+    fn read_enum<T, F>(&mut self, name: &str, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>) -> DecodeResult<T>,
+    {
+        self.typed = name == AMQP_TYPE;
+        f(self)
+    }
+
+    fn read_enum_variant<T, F>(&mut self, names: &[&str], mut f: F) -> DecodeResult<T> where
+        F: FnMut(&mut Decoder<'d>, usize) -> DecodeResult<T>,
+    {
+        if self.typed {
+            self.typed = false;
+            return self.read_typed(names, f);
+        }
+
+        let find = |name: string::String| -> DecodeResult<usize> {
+            match names.iter().position(|n| *n == &name[..]) {
+                Some(idx) => Ok(idx),
+                None => Err(DecoderError::UnknownVariantError(name))
+            }
+        };
+
+        match self.peek() {
+            Some(proton_sys::PN_LIST) => {
+                try!(self.next());
+                self.nested(|d| {
+                    let idx = try!(find(try!(serialize::Decoder::read_str(d))));
+                    f(d, idx)
+                })
+            },
+            Some(proton_sys::PN_STRING) | Some(proton_sys::PN_SYMBOL) => {
+                let idx = try!(find(try!(serialize::Decoder::read_str(self))));
+                f(self, idx)
+            },
+            Some(t) => Err(expected("PN_STRING", t)),
+            None => Err(syntax_error(ErrorCode::UnexpectedEndOfData))
+        }
+    }
+
+    fn read_enum_variant_arg<T, F>(&mut self, _idx: usize, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>) -> DecodeResult<T>,
+    {
+        match self.element_descriptor.take() {
+            Some(descriptor) => f(&mut Decoder::from_data(descriptor)),
+            None => f(self)
+        }
+    }
+
+    fn read_enum_struct_variant<T, F>(&mut self, names: &[&str], f: F) -> DecodeResult<T> where
+        F: FnMut(&mut Decoder<'d>, usize) -> DecodeResult<T>,
+    {
+        self.read_enum_variant(names, f)
+    }
+
+    fn read_enum_struct_variant_field<T, F>(&mut self,
+                                            _name: &str,
+                                            idx: usize,
+                                            f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>) -> DecodeResult<T>,
+    {
+        self.read_enum_variant_arg(idx, f)
+    }
+
+    fn read_struct<T, F>(&mut self, _name: &str, _len: usize, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>) -> DecodeResult<T>,
+    {
+        match try!(self.next()) {
+            proton_sys::PN_LIST => self.nested(f),
+            t => Err(expected("PN_LIST", t))
+        }
+    }
+
+    fn read_struct_field<T, F>(&mut self, _name: &str, _idx: usize, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>) -> DecodeResult<T>,
+    {
+        f(self)
+    }
+
+    fn read_tuple<T, F>(&mut self, len: usize, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>) -> DecodeResult<T>,
+    {
+        self.read_seq(move |d, actual| {
+            if actual != len {
+                return Err(DecoderError::ExpectedError(format!("tuple of {} elements", len),
+                                                       format!("{} elements", actual)));
+            }
+            f(d)
+        })
+    }
+    fn read_tuple_arg<T, F>(&mut self, idx: usize, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>) -> DecodeResult<T>,
+    {
+        self.read_seq_elt(idx, f)
+    }
+
+    fn read_tuple_struct<T, F>(&mut self, _name: &str, len: usize, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>) -> DecodeResult<T>,
+    {
+        self.read_tuple(len, f)
+    }
+    fn read_tuple_struct_arg<T, F>(&mut self, idx: usize, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>) -> DecodeResult<T>,
+    {
+        self.read_tuple_arg(idx, f)
+    }
+
+    fn read_option<T, F>(&mut self, mut f: F) -> DecodeResult<T> where
+        F: FnMut(&mut Decoder<'d>, bool) -> DecodeResult<T>,
+    {
+        // Trailing fields of a list may be omitted, they read as None.
+        match self.peek() {
+            None => f(self, false),
+            Some(proton_sys::PN_NULL) => {
+                try!(self.next());
+                f(self, false)
+            },
+            Some(_) => f(self, true)
+        }
+    }
+
+    fn read_seq<T, F>(&mut self, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>, usize) -> DecodeResult<T>,
+    {
+        let hint = self.hint.take();
+        match try!(self.next()) {
+            proton_sys::PN_BINARY => {
+                let bytes = unsafe{bytes_as_slice(proton_sys::pn_data_get_binary(self.data))};
+                self.read_bytes(bytes.to_vec(), f)
+            },
+            t @ proton_sys::PN_UUID if hint == Some(t) => {
+                let uuid = unsafe{proton_sys::pn_data_get_uuid(self.data)};
+                self.read_bytes(uuid.bytes.iter().map(|b| *b as u8).collect(), f)
+            },
+            t @ proton_sys::PN_DECIMAL128 if hint == Some(t) => {
+                let decimal = unsafe{proton_sys::pn_data_get_decimal128(self.data)};
+                self.read_bytes(decimal.bytes.iter().map(|b| *b as u8).collect(), f)
+            },
+            proton_sys::PN_LIST => {
+                let len = unsafe{proton_sys::pn_data_get_list(self.data)} as usize;
+                self.nested(|d| f(d, len))
+            },
+            proton_sys::PN_ARRAY => {
+                let len = unsafe{proton_sys::pn_data_get_array(self.data)} as usize;
+                let described = unsafe{proton_sys::pn_data_is_array_described(self.data) != 0};
+                self.nested(|d| {
+                    // the descriptor is the first child of a described array,
+                    // kept aside for elements read as described values
+                    if described {
+                        try!(d.next());
+                        let descriptor = try!(Value::get(d.data));
+                        let data = unsafe{proton_sys::pn_data(16)};
+                        if let Err(e) = descriptor.put(data) {
+                            unsafe {proton_sys::pn_data_free(data)};
+                            return Err(e);
+                        }
+                        d.descriptor = Some(data);
+                    }
+                    let result = f(d, len);
+                    if let Some(data) = d.descriptor.take() {
+                        unsafe {proton_sys::pn_data_free(data)};
+                    }
+                    result
+                })
+            },
+            t => Err(expected("PN_LIST", t))
+        }
+    }
+
+    fn read_seq_elt<T, F>(&mut self, _idx: usize, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>) -> DecodeResult<T>,
+    {
+        f(self)
+    }
+
+    fn read_map<T, F>(&mut self, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>, usize) -> DecodeResult<T>,
+    {
+        match try!(self.next()) {
+            proton_sys::PN_MAP => {
+                // pn_data_get_map counts keys and values
+                let len = unsafe{proton_sys::pn_data_get_map(self.data)} as usize / 2;
+                self.nested(|d| f(d, len))
+            },
+            t => Err(expected("PN_MAP", t))
+        }
+    }
+
+    fn read_map_elt_key<T, F>(&mut self, _idx: usize, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>) -> DecodeResult<T>,
+    {
+        f(self)
+    }
+
+    fn read_map_elt_val<T, F>(&mut self, _idx: usize, f: F) -> DecodeResult<T> where
+        F: FnOnce(&mut Decoder<'d>) -> DecodeResult<T>,
+    {
+        f(self)
+    }
+
+    fn error(&mut self, err: &str) -> DecoderError {
+        DecoderError::ApplicationError(err.to_string())
+    }
+}
+
+/// Shortcut function to decode a `T` from AMQP encoded bytes
+pub fn decode<T: serialize::Decodable>(bytes: &[u8]) -> DecodeResult<T> {
+    let mut decoder = try!(Decoder::new(bytes));
+    serialize::Decodable::decode(&mut decoder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::encoder::{ErrorCode, ParserError};
+    use std::ffi::CString;
+    use proton_sys;
+
+    /// Encodes `data` and frees it.
+    fn encode_data(data: *mut proton_sys::pn_data_t) -> Vec<u8> {
+        let mut bytes = vec![0u8; 1024];
+        let size = unsafe {
+            let size = proton_sys::pn_data_encode(data,
+                                                  bytes.as_mut_ptr() as *mut ::libc::c_char,
+                                                  bytes.len() as proton_sys::size_t);
+            proton_sys::pn_data_free(data);
+            size
+        };
+        assert!(size > 0);
+        bytes.truncate(size as usize);
+        bytes
+    }
+
+    fn put_string(data: *mut proton_sys::pn_data_t, value: &str) {
+        let s = CString::new(value).unwrap();
+        unsafe {
+            let bytes = proton_sys::pn_bytes(value.len() as proton_sys::size_t, s.as_ptr());
+            proton_sys::pn_data_put_string(data, bytes);
+        }
+    }
+
+    macro_rules! create_test {
+        ($func:ident, $put_data:ident, $t:ty, $value:expr) => (
+            #[test]
+            fn $func() {
+                let value: $t = $value;
+                let bytes = unsafe {
+                    let data = proton_sys::pn_data(16);
+                    proton_sys::$put_data(data, value);
+                    encode_data(data)
+                };
+                assert_eq!(value, decode::<$t>(&bytes).unwrap());
+            }
+        )
+    }
+
+    create_test!(ubyte, pn_data_put_ubyte, u8, 1u8);
+    create_test!(ushort, pn_data_put_ushort, u16, 1u16);
+    create_test!(uint, pn_data_put_uint, u32, 1u32);
+    create_test!(ulong, pn_data_put_ulong, u64, 1u64);
+
+    create_test!(byte, pn_data_put_byte, i8, 1i8);
+    create_test!(short, pn_data_put_short, i16, 1i16);
+    create_test!(int, pn_data_put_int, i32, 1i32);
+    create_test!(long, pn_data_put_long, i64, 1i64);
+
+    create_test!(float, pn_data_put_float, f32, 1f32);
+    create_test!(double, pn_data_put_double, f64, 1f64);
+
+    #[test]
+    fn test_string_decoding() {
+        let bytes = unsafe {
+            let data = proton_sys::pn_data(16);
+            put_string(data, "testing");
+            encode_data(data)
+        };
+        assert_eq!("testing".to_string(), decode::<String>(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_widening_and_narrowing() {
+        let bytes = unsafe {
+            let data = proton_sys::pn_data(16);
+            proton_sys::pn_data_put_ubyte(data, 200);
+            encode_data(data)
+        };
+        assert_eq!(200u64, decode::<u64>(&bytes).unwrap());
+        assert!(decode::<i8>(&bytes).is_err());
+
+        let bytes = unsafe {
+            let data = proton_sys::pn_data(16);
+            proton_sys::pn_data_put_uint(data, 70000);
+            encode_data(data)
+        };
+        assert!(decode::<u16>(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_list_decoding() {
+        let bytes = unsafe {
+            let data = proton_sys::pn_data(16);
+            proton_sys::pn_data_put_list(data);
+            proton_sys::pn_data_enter(data);
+            proton_sys::pn_data_put_int(data, 1);
+            proton_sys::pn_data_put_int(data, 2);
+            proton_sys::pn_data_put_int(data, 3);
+            proton_sys::pn_data_exit(data);
+            encode_data(data)
+        };
+        assert_eq!(vec![1, 2, 3], decode::<Vec<i32>>(&bytes).unwrap());
+        assert_eq!((1, 2, 3), decode::<(i32, i32, i32)>(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_map_decoding() {
+        use std::collections::HashMap;
+
+        let bytes = unsafe {
+            let data = proton_sys::pn_data(16);
+            proton_sys::pn_data_put_map(data);
+            proton_sys::pn_data_enter(data);
+            put_string(data, "one");
+            proton_sys::pn_data_put_long(data, 1);
+            put_string(data, "two");
+            proton_sys::pn_data_put_long(data, 2);
+            proton_sys::pn_data_exit(data);
+            encode_data(data)
+        };
+        let map = decode::<HashMap<String, i64>>(&bytes).unwrap();
+        assert_eq!(2, map.len());
+        assert_eq!(Some(&1), map.get("one"));
+        assert_eq!(Some(&2), map.get("two"));
+    }
+
+    #[test]
+    fn test_option_decoding() {
+        let bytes = unsafe {
+            let data = proton_sys::pn_data(16);
+            proton_sys::pn_data_put_null(data);
+            encode_data(data)
+        };
+        assert_eq!(None, decode::<Option<u32>>(&bytes).unwrap());
+
+        let bytes = unsafe {
+            let data = proton_sys::pn_data(16);
+            proton_sys::pn_data_put_uint(data, 7);
+            encode_data(data)
+        };
+        assert_eq!(Some(7), decode::<Option<u32>>(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        let bytes = unsafe {
+            let data = proton_sys::pn_data(16);
+            proton_sys::pn_data_put_bool(data, 1);
+            encode_data(data)
+        };
+        match decode::<String>(&bytes) {
+            Err(DecoderError::ExpectedError(_, found)) => assert_eq!("PN_BOOL", found),
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_truncated_input() {
+        let bytes = unsafe {
+            let data = proton_sys::pn_data(16);
+            put_string(data, "testing");
+            encode_data(data)
+        };
+        match decode::<String>(&bytes[..bytes.len() - 2]) {
+            Err(DecoderError::ParseError(ParserError::SyntaxError(code, _, _))) => {
+                assert_eq!(ErrorCode::UnexpectedEndOfData, code)
+            },
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+}
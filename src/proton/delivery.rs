@@ -0,0 +1,322 @@
+use proton_sys;
+
+use super::{Delivery, Link, Condition, check_value};
+use super::error::Result;
+use super::decoder::bytes_as_slice;
+use super::types::Symbol;
+use super::value::Value;
+
+/// Outcome or progress of a delivery, as set by either end.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeliveryState {
+    /// how much of the delivery has been received so far
+    Received,
+    Accepted,
+    Rejected,
+    Released,
+    Modified,
+    /// a state defined outside the core spec, such as a transactional one
+    Other(u64),
+}
+
+impl DeliveryState {
+    /// `None` when no state has been set.
+    pub fn from_code(code: u64) -> Option<DeliveryState> {
+        match code {
+            0 => None,
+            proton_sys::PN_RECEIVED => Some(DeliveryState::Received),
+            proton_sys::PN_ACCEPTED => Some(DeliveryState::Accepted),
+            proton_sys::PN_REJECTED => Some(DeliveryState::Rejected),
+            proton_sys::PN_RELEASED => Some(DeliveryState::Released),
+            proton_sys::PN_MODIFIED => Some(DeliveryState::Modified),
+            code => Some(DeliveryState::Other(code))
+        }
+    }
+
+    pub fn code(&self) -> u64 {
+        match *self {
+            DeliveryState::Received => proton_sys::PN_RECEIVED,
+            DeliveryState::Accepted => proton_sys::PN_ACCEPTED,
+            DeliveryState::Rejected => proton_sys::PN_REJECTED,
+            DeliveryState::Released => proton_sys::PN_RELEASED,
+            DeliveryState::Modified => proton_sys::PN_MODIFIED,
+            DeliveryState::Other(code) => code,
+        }
+    }
+}
+
+/// Outcome of a delivery, as decided by its receiver.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Outcome {
+    /// the message was processed
+    Accepted,
+    /// the message is invalid and won't be processed, e.g. a poison message
+    Rejected(Option<Condition>),
+    /// the message wasn't processed and can be redelivered
+    Released,
+    /// like `Released`, with changes for the next delivery attempt
+    Modified {
+        /// counts as a failed delivery attempt
+        failed: bool,
+        /// shouldn't be redelivered to this receiver
+        undeliverable: bool,
+        /// merged into the message annotations
+        annotations: Vec<(Symbol, Value)>
+    },
+}
+
+impl Delivery {
+    /// Starts a new delivery on `link`. It becomes the link's current
+    /// delivery if there's none yet.
+    pub fn new(link: &mut Link, tag: &[u8]) -> Delivery {
+        let tag = super::encoder::pn_bytes(tag);
+        Delivery::from_ptr(unsafe {proton_sys::pn_delivery(link.as_ptr(), tag)})
+    }
+
+    pub fn tag(&self) -> &[u8] {
+        unsafe {bytes_as_slice(proton_sys::pn_delivery_tag(self.0))}
+    }
+
+    pub fn link(&self) -> Link {
+        Link::from_ptr(unsafe {proton_sys::pn_delivery_link(self.0)})
+    }
+
+    /// Whether there's data to read, on an incoming delivery.
+    pub fn readable(&self) -> bool {
+        unsafe {proton_sys::pn_delivery_readable(self.0) != 0}
+    }
+
+    /// Whether data can be sent, on an outgoing delivery.
+    pub fn writable(&self) -> bool {
+        unsafe {proton_sys::pn_delivery_writable(self.0) != 0}
+    }
+
+    /// Whether more data is still to come for an incoming delivery.
+    pub fn partial(&self) -> bool {
+        unsafe {proton_sys::pn_delivery_partial(self.0) != 0}
+    }
+
+    /// Bytes received but not read yet, or sent but not written out yet.
+    pub fn pending(&self) -> usize {
+        unsafe {proton_sys::pn_delivery_pending(self.0) as usize}
+    }
+
+    /// Whether the peer changed the state of the delivery since `clear`
+    /// was last called.
+    pub fn updated(&self) -> bool {
+        unsafe {proton_sys::pn_delivery_updated(self.0) != 0}
+    }
+
+    pub fn clear(&mut self) {
+        unsafe {proton_sys::pn_delivery_clear(self.0)};
+    }
+
+    pub fn local_state(&self) -> Option<DeliveryState> {
+        DeliveryState::from_code(unsafe {proton_sys::pn_delivery_local_state(self.0)})
+    }
+
+    pub fn remote_state(&self) -> Option<DeliveryState> {
+        DeliveryState::from_code(unsafe {proton_sys::pn_delivery_remote_state(self.0)})
+    }
+
+    /// Sets the local state, sent to the peer with the next disposition.
+    pub fn update(&mut self, state: DeliveryState) {
+        unsafe {proton_sys::pn_delivery_update(self.0, state.code())};
+    }
+
+    /// Marks the message as processed.
+    pub fn accept(&mut self) {
+        self.update(DeliveryState::Accepted);
+    }
+
+    /// Refuses the message for good, telling the peer why.
+    pub fn reject(&mut self, condition: &Condition) -> Result<()> {
+        let local = unsafe {proton_sys::pn_delivery_local(self.0)};
+        try!(condition.put(unsafe {proton_sys::pn_disposition_condition(local)}));
+        self.update(DeliveryState::Rejected);
+        Ok(())
+    }
+
+    /// Gives the message back for redelivery.
+    pub fn release(&mut self) {
+        self.update(DeliveryState::Released);
+    }
+
+    /// Gives the message back for redelivery, with `annotations` to add to
+    /// it. Fails if an annotation isn't a valid value.
+    pub fn modify(&mut self, failed: bool, undeliverable: bool,
+                  annotations: &[(Symbol, Value)]) -> Result<()> {
+        let map = if annotations.is_empty() {
+            None
+        } else {
+            let map = Value::Map(annotations.iter()
+                                 .map(|&(ref k, ref v)| (Value::Symbol(k.0.clone()), v.clone()))
+                                 .collect());
+            try!(check_value(&map));
+            Some(map)
+        };
+
+        unsafe {
+            let local = proton_sys::pn_delivery_local(self.0);
+            proton_sys::pn_disposition_set_failed(local, failed as u8);
+            proton_sys::pn_disposition_set_undeliverable(local, undeliverable as u8);
+            // checked above, so this can't fail
+            let _ = Value::set_data(proton_sys::pn_disposition_annotations(local), map.as_ref());
+        }
+        self.update(DeliveryState::Modified);
+        Ok(())
+    }
+
+    /// The outcome the peer decided on, if it has.
+    pub fn remote_outcome(&self) -> Option<Outcome> {
+        let remote = unsafe {proton_sys::pn_delivery_remote(self.0)};
+        match self.remote_state() {
+            Some(DeliveryState::Accepted) => Some(Outcome::Accepted),
+            Some(DeliveryState::Rejected) => {
+                let condition = unsafe {proton_sys::pn_disposition_condition(remote)};
+                Some(Outcome::Rejected(Condition::from_ptr(condition)))
+            },
+            Some(DeliveryState::Released) => Some(Outcome::Released),
+            Some(DeliveryState::Modified) => unsafe {
+                let annotations = match Value::from_data(proton_sys::pn_disposition_annotations(remote)) {
+                    Ok(Some(Value::Map(entries))) => entries.into_iter().filter_map(|(k, v)| match k {
+                        Value::Symbol(k) => Some((Symbol(k), v)),
+                        _ => None
+                    }).collect(),
+                    _ => Vec::new()
+                };
+                Some(Outcome::Modified {
+                    failed: proton_sys::pn_disposition_is_failed(remote) != 0,
+                    undeliverable: proton_sys::pn_disposition_is_undeliverable(remote) != 0,
+                    annotations: annotations
+                })
+            },
+            _ => None
+        }
+    }
+
+    /// Whether the peer settled the delivery.
+    pub fn settled(&self) -> bool {
+        unsafe {proton_sys::pn_delivery_settled(self.0) != 0}
+    }
+
+    /// Settles the delivery locally. Proton forgets about it, but the
+    /// handle stays valid.
+    pub fn settle(&mut self) {
+        unsafe {proton_sys::pn_delivery_settle(self.0)};
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Connection, Endpoint};
+    use super::super::tests::Pair;
+
+    #[test]
+    fn test_new_delivery() {
+        let mut connection = Connection::new();
+        let mut link = connection.session().sender("orders").unwrap();
+        assert!(link.current().is_none());
+
+        let delivery = Delivery::new(&mut link, b"tag-1");
+        assert_eq!(b"tag-1", delivery.tag());
+        assert_eq!(link.as_ptr(), delivery.link().as_ptr());
+        assert_eq!(delivery.as_ptr(), link.current().unwrap().as_ptr());
+
+        // no credit yet
+        assert!(!delivery.writable());
+        assert!(!delivery.readable());
+        assert_eq!(None, delivery.local_state());
+        assert_eq!(None, delivery.remote_state());
+        assert!(!delivery.settled());
+    }
+
+    #[test]
+    fn test_update_and_settle() {
+        let mut connection = Connection::new();
+        let mut link = connection.session().sender("orders").unwrap();
+        link.open();
+        let mut delivery = Delivery::new(&mut link, b"tag-1");
+        assert!(link.advance());
+
+        delivery.update(DeliveryState::Released);
+        assert_eq!(Some(DeliveryState::Released), delivery.local_state());
+        delivery.settle();
+        assert_eq!(b"tag-1", delivery.tag());
+    }
+
+    #[test]
+    fn test_state_codes() {
+        assert_eq!(None, DeliveryState::from_code(0));
+        assert_eq!(Some(DeliveryState::Accepted), DeliveryState::from_code(0x24));
+        assert_eq!(Some(DeliveryState::Other(0x34)), DeliveryState::from_code(0x34));
+        assert_eq!(0x27, DeliveryState::Modified.code());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut pair = Pair::new();
+        let mut outgoing = Delivery::new(&mut pair.sender, b"tag-1");
+        assert!(outgoing.writable());
+        match pair.sender {
+            Link::Sender(ref mut sender) => assert_eq!(5, sender.send(b"hello").unwrap()),
+            _ => unreachable!()
+        }
+        assert!(pair.sender.advance());
+        pair.pump();
+
+        let mut incoming = pair.receiver.current().unwrap();
+        assert_eq!(b"tag-1", incoming.tag());
+        assert!(incoming.readable());
+        assert!(!incoming.partial());
+        assert_eq!(5, incoming.pending());
+
+        incoming.update(DeliveryState::Accepted);
+        incoming.settle();
+        pair.pump();
+
+        assert!(outgoing.updated());
+        assert_eq!(Some(DeliveryState::Accepted), outgoing.remote_state());
+        assert!(outgoing.settled());
+        outgoing.settle();
+    }
+
+    #[test]
+    fn test_reject_with_condition() {
+        let mut pair = Pair::new();
+        let (outgoing, mut incoming) = pair.transfer(b"1", b"not json");
+        let condition = Condition::new("amqp:decode-error", "body is not valid JSON");
+        incoming.reject(&condition).unwrap();
+        incoming.settle();
+        pair.pump();
+
+        assert_eq!(Some(DeliveryState::Rejected), outgoing.remote_state());
+        assert_eq!(Some(Outcome::Rejected(Some(condition))), outgoing.remote_outcome());
+    }
+
+    #[test]
+    fn test_release_and_modify() {
+        let mut pair = Pair::new();
+        let (released, mut incoming) = pair.transfer(b"1", b"a");
+        incoming.release();
+        incoming.settle();
+        let (modified, mut incoming) = pair.transfer(b"2", b"b");
+        let mixed = Value::Array(vec![Value::Int(1), Value::Bool(true)]);
+        assert!(incoming.modify(true, false, &[(Symbol::from("x-opt-mixed"), mixed)]).is_err());
+        assert_eq!(None, incoming.local_state());
+        let annotations = vec![(Symbol::from("x-opt-retry"), Value::Int(1))];
+        incoming.modify(true, false, &annotations).unwrap();
+        incoming.settle();
+        let (accepted, mut incoming) = pair.transfer(b"3", b"c");
+        incoming.accept();
+        incoming.settle();
+        pair.pump();
+
+        assert_eq!(Some(Outcome::Released), released.remote_outcome());
+        assert_eq!(Some(Outcome::Modified {failed: true, undeliverable: false, annotations: annotations}),
+                   modified.remote_outcome());
+        assert_eq!(Some(Outcome::Accepted), accepted.remote_outcome());
+        assert!(accepted.settled());
+    }
+}
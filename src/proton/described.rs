@@ -0,0 +1,246 @@
+//! AMQP described types.
+//!
+//! A described type is a value prefixed by a descriptor, either a symbol
+//! such as `"com.example:order"` or a ulong code made of a 32 bit domain id
+//! and a 32 bit descriptor id. This is how AMQP marks composite types, the
+//! performatives and message sections being the best known ones.
+//!
+//! `amqp_described!` maps a struct to a described list of its fields:
+//!
+//! ```ignore
+//! struct Order {
+//!     id: u64,
+//!     item: String,
+//! }
+//!
+//! amqp_described!(Order, "com.example:order", {id, item});
+//! ```
+
+use std::fmt;
+use rustc_serialize::{Encodable, Decodable, Encoder, Decoder};
+
+use super::encoder::AMQP_TYPE;
+use super::value::Value;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Descriptor {
+    /// symbolic descriptor, e.g. `"com.example:order"`
+    Name(&'static str),
+    /// numeric descriptor, `domain << 32 | id`
+    Code(u64),
+}
+
+impl Descriptor {
+    /// Builds a numeric descriptor out of a domain id and a descriptor id.
+    pub fn numeric(domain: u32, id: u32) -> Descriptor {
+        Descriptor::Code((domain as u64) << 32 | id as u64)
+    }
+
+    pub fn to_value(&self) -> Value {
+        match *self {
+            Descriptor::Name(name) => Value::Symbol(name.to_string()),
+            Descriptor::Code(code) => Value::Ulong(code),
+        }
+    }
+
+    /// Whether `value`, as found in front of a described value, is this
+    /// descriptor.
+    pub fn matches(&self, value: &Value) -> bool {
+        match (*self, value) {
+            (Descriptor::Name(name), &Value::Symbol(ref s)) => name == &s[..],
+            (Descriptor::Code(code), &Value::Ulong(c)) => code == c,
+            _ => false
+        }
+    }
+
+    /// Emits a described value with this descriptor, `f` emitting the value.
+    pub fn emit<S, F>(&self, s: &mut S, f: F) -> Result<(), S::Error> where
+        S: Encoder,
+        F: FnOnce(&mut S) -> Result<(), S::Error>,
+    {
+        s.emit_enum(AMQP_TYPE, |s| {
+            s.emit_enum_variant("Described", 24, 2, |s| {
+                try!(s.emit_enum_variant_arg(0, |s| self.to_value().encode(s)));
+                s.emit_enum_variant_arg(1, f)
+            })
+        })
+    }
+
+    /// Reads a described value, failing unless its descriptor is this one.
+    /// `f` reads the value.
+    pub fn read<D, T, F>(&self, d: &mut D, f: F) -> Result<T, D::Error> where
+        D: Decoder,
+        F: FnOnce(&mut D) -> Result<T, D::Error>,
+    {
+        let mut f = Some(f);
+        d.read_enum(AMQP_TYPE, |d| {
+            d.read_enum_variant(&["Described"], |d, _| {
+                let descriptor: Value = try!(d.read_enum_variant_arg(0, Decodable::decode));
+                if !self.matches(&descriptor) {
+                    return Err(d.error(&format!("expected descriptor {}, found {:?}",
+                                                self, descriptor)));
+                }
+
+                match f.take() {
+                    Some(f) => d.read_enum_variant_arg(1, f),
+                    None => Err(d.error("described value read twice"))
+                }
+            })
+        })
+    }
+}
+
+impl From<&'static str> for Descriptor {
+    fn from(name: &'static str) -> Descriptor {
+        Descriptor::Name(name)
+    }
+}
+
+impl From<u64> for Descriptor {
+    fn from(code: u64) -> Descriptor {
+        Descriptor::Code(code)
+    }
+}
+
+impl fmt::Display for Descriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Descriptor::Name(name) => write!(f, "{}", name),
+            Descriptor::Code(code) => write!(f, "0x{:08x}:{:08x}", code >> 32, code & 0xffffffff),
+        }
+    }
+}
+
+/// Types that go on the wire as AMQP described types.
+pub trait Described {
+    fn descriptor() -> Descriptor;
+}
+
+/// Implements `Described`, `Encodable` and `Decodable` for a struct so that
+/// it's encoded as a described list of the given fields, in that order.
+/// The descriptor is anything `Descriptor` converts from: a `&'static str`
+/// for a symbolic descriptor, a `u64` or `Descriptor::numeric(domain, id)`
+/// for a numeric one.
+#[macro_export]
+macro_rules! amqp_described {
+    ($name:ident, $descriptor:expr, {$($field:ident),*}) => (
+        impl $crate::Described for $name {
+            fn descriptor() -> $crate::Descriptor {
+                $crate::Descriptor::from($descriptor)
+            }
+        }
+
+        impl $crate::rustc_serialize::Encodable for $name {
+            #[allow(unused_mut, unused_assignments)]
+            fn encode<S: $crate::rustc_serialize::Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+                let count = [$(stringify!($field)),*].len();
+                <$name as $crate::Described>::descriptor().emit(s, |s| {
+                    s.emit_struct(stringify!($name), count, |s| {
+                        let mut idx = 0;
+                        $(
+                            try!(s.emit_struct_field(stringify!($field), idx, |s| {
+                                $crate::rustc_serialize::Encodable::encode(&self.$field, s)
+                            }));
+                            idx += 1;
+                        )*
+                        Ok(())
+                    })
+                })
+            }
+        }
+
+        impl $crate::rustc_serialize::Decodable for $name {
+            #[allow(unused_mut, unused_assignments)]
+            fn decode<D: $crate::rustc_serialize::Decoder>(d: &mut D) -> Result<$name, D::Error> {
+                let count = [$(stringify!($field)),*].len();
+                <$name as $crate::Described>::descriptor().read(d, |d| {
+                    d.read_struct(stringify!($name), count, |d| {
+                        let mut idx = 0;
+                        Ok($name {
+                            $(
+                                $field: try!(d.read_struct_field(stringify!($field), {idx += 1; idx - 1},
+                                                                 $crate::rustc_serialize::Decodable::decode)),
+                            )*
+                        })
+                    })
+                })
+            }
+        }
+    );
+    ($name:ident, $descriptor:expr, {$($field:ident),*,}) => (
+        amqp_described!($name, $descriptor, {$($field),*});
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::value::Value;
+    use super::super::encoder::encode;
+    use super::super::decoder::decode;
+
+    #[derive(PartialEq, Debug)]
+    struct Order {
+        id: u64,
+        item: String,
+        quantity: Option<u32>,
+    }
+
+    amqp_described!(Order, "com.example:order", {id, item, quantity});
+
+    #[derive(PartialEq, Debug)]
+    struct Refund {
+        order: Order,
+        reason: String,
+    }
+
+    amqp_described!(Refund, Descriptor::numeric(0x1234, 1), {order, reason});
+
+    fn order() -> Order {
+        Order {id: 7, item: "widget".to_string(), quantity: Some(3)}
+    }
+
+    #[test]
+    fn test_symbolic_descriptor() {
+        let encoded = encode(&order()).unwrap();
+        assert_eq!(Value::Described(
+            Box::new(Value::Symbol("com.example:order".to_string())),
+            Box::new(Value::List(vec![Value::Ulong(7),
+                                      Value::String("widget".to_string()),
+                                      Value::Uint(3)]))),
+            decode::<Value>(&encoded).unwrap());
+        assert_eq!(order(), decode(&encoded).unwrap());
+    }
+
+    #[test]
+    fn test_numeric_descriptor() {
+        let refund = Refund {order: order(), reason: "broken".to_string()};
+        let encoded = encode(&refund).unwrap();
+        match decode::<Value>(&encoded).unwrap() {
+            Value::Described(descriptor, _) => assert_eq!(Value::Ulong(0x0000123400000001), *descriptor),
+            v => panic!("expected a described value, got {:?}", v)
+        }
+        assert_eq!(refund, decode(&encoded).unwrap());
+    }
+
+    #[test]
+    fn test_wrong_descriptor() {
+        let value = Value::Described(Box::new(Value::Symbol("com.example:invoice".to_string())),
+                                     Box::new(Value::List(vec![])));
+        let encoded = encode(&value).unwrap();
+        assert!(decode::<Order>(&encoded).is_err());
+    }
+
+    #[test]
+    fn test_sequence_of_described() {
+        let orders = vec![order(), Order {id: 8, item: "gadget".to_string(), quantity: None}];
+        let encoded = encode(&orders).unwrap();
+        assert_eq!(orders, decode::<Vec<Order>>(&encoded).unwrap());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!("com.example:order", Descriptor::Name("com.example:order").to_string());
+        assert_eq!("0x00001234:00000001", Descriptor::numeric(0x1234, 1).to_string());
+    }
+}
@@ -5,6 +5,8 @@ use rustc_serialize as serialize;
 use std::marker::PhantomData;
 use proton_sys;
 
+use super::value::Value;
+
 /*
 Marshal encodes a Rust value as AMQP data in buffer based on its type.
 If buffer is nil, or is not large enough, a new buffer  is created.
@@ -26,32 +28,41 @@ Rust types are encoded as follows
  +-------------------------------------+--------------------------------------------+
  |string                               |string                                      |
  +-------------------------------------+--------------------------------------------+
- |[]byte, Binary                       |binary                                      |
+ |types::Binary                        |binary                                      |
  +-------------------------------------+--------------------------------------------+
- |Symbol                               |symbol                                      |
+ |types::Symbol                        |symbol                                      |
  +-------------------------------------+--------------------------------------------+
- |interface{}                          |the contained type                          |
+ |types::Timestamp, types::Uuid        |timestamp, uuid                             |
  +-------------------------------------+--------------------------------------------+
- |nil                                  |null                                        |
+ |types::Decimal32/64/128              |decimal32, decimal64, decimal128            |
  +-------------------------------------+--------------------------------------------+
- |map[K]T                              |map with K and T converted as above         |
+ |Value                                |the AMQP type of the variant                |
  +-------------------------------------+--------------------------------------------+
- |Map                                  |map, may have mixed types for keys, values  |
+ |()                                   |null                                        |
  +-------------------------------------+--------------------------------------------+
- |[]T                                  |list with T converted as above              |
+ |Option<T>                            |null or T converted as above                |
  +-------------------------------------+--------------------------------------------+
- |List                                 |list, may have mixed types  values          |
+ |HashMap<K, T>, BTreeMap<K, T>        |map with K and T converted as above         |
+ +-------------------------------------+--------------------------------------------+
+ |Vec<T>, [T] of a single scalar type  |array with T converted as above             |
+ +-------------------------------------+--------------------------------------------+
+ |Vec<T>, [T] of mixed or compound T   |list with T converted as above              |
+ +-------------------------------------+--------------------------------------------+
+ |tuples, structs, tuple structs       |list of the fields, in declaration order    |
+ +-------------------------------------+--------------------------------------------+
+ |enum variant without arguments       |string holding the variant name             |
+ +-------------------------------------+--------------------------------------------+
+ |enum variant with arguments          |list of the variant name and its arguments  |
  +-------------------------------------+--------------------------------------------+
-
-TODO: Update the table to match rust types
-TODO Rust types: array, slice, struct
 
 Rust types that cannot be marshaled: complex64/128, uintptr, function, interface, channel
 */
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum ErrorCode {
-// ADD SOMETHING HERE
+    InvalidData,
+    UnexpectedEndOfData,
+    TrailingBytes,
 }
 
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -64,27 +75,75 @@ pub enum ParserError {
 // Builder and Parser have the same errors.
 pub type BuilderError = ParserError;
 
-#[derive(Clone, PartialEq, Debug)]
-pub enum DecoderError {
-    ParseError(ParserError),
-    ExpectedError(string::String, string::String),
-    MissingFieldError(string::String),
-    UnknownVariantError(string::String),
-    ApplicationError(string::String)
-}
-
-
 #[derive(Clone, Copy, Debug)]
 pub enum EncoderError {
     FmtError(fmt::Error),
     BadHashmapKey,
+    DataError(i32),
 }
 
 pub type EncodeResult = Result<(), EncoderError>;
-pub type DecodeResult<T> = Result<T, DecoderError>;
+
+/// Name of the enum whose variants are AMQP types rather than user defined
+/// variants. `Value` and the wrappers in `types` emit themselves as a
+/// variant of this enum, and the Encoder and Decoder map the variant to the
+/// AMQP type of the same name instead of a string or list holding the
+/// variant name. Other encoders just see a regular enum.
+pub const AMQP_TYPE: &'static str = "amqp:type";
+
+static TYPE_VARIANTS: [(&'static str, proton_sys::pn_type_t); 25] = [
+    ("Null", proton_sys::PN_NULL),
+    ("Bool", proton_sys::PN_BOOL),
+    ("Ubyte", proton_sys::PN_UBYTE),
+    ("Ushort", proton_sys::PN_USHORT),
+    ("Uint", proton_sys::PN_UINT),
+    ("Ulong", proton_sys::PN_ULONG),
+    ("Byte", proton_sys::PN_BYTE),
+    ("Short", proton_sys::PN_SHORT),
+    ("Int", proton_sys::PN_INT),
+    ("Long", proton_sys::PN_LONG),
+    ("Float", proton_sys::PN_FLOAT),
+    ("Double", proton_sys::PN_DOUBLE),
+    ("Char", proton_sys::PN_CHAR),
+    ("Timestamp", proton_sys::PN_TIMESTAMP),
+    ("Decimal32", proton_sys::PN_DECIMAL32),
+    ("Decimal64", proton_sys::PN_DECIMAL64),
+    ("Decimal128", proton_sys::PN_DECIMAL128),
+    ("Uuid", proton_sys::PN_UUID),
+    ("Binary", proton_sys::PN_BINARY),
+    ("String", proton_sys::PN_STRING),
+    ("Symbol", proton_sys::PN_SYMBOL),
+    ("List", proton_sys::PN_LIST),
+    ("Map", proton_sys::PN_MAP),
+    ("Array", proton_sys::PN_ARRAY),
+    ("Described", proton_sys::PN_DESCRIBED),
+];
+
+pub fn type_for_variant(name: &str) -> Option<proton_sys::pn_type_t> {
+    TYPE_VARIANTS.iter().find(|&&(n, _)| n == name).map(|&(_, t)| t)
+}
+
+pub fn variant_for_type(t: proton_sys::pn_type_t) -> Option<&'static str> {
+    TYPE_VARIANTS.iter().find(|&&(_, tt)| tt == t).map(|&(n, _)| n)
+}
+
+/// Wraps `bytes` in a `pn_bytes_t`. Proton copies the bytes when they're
+/// put in a `pn_data_t`, so the result only needs to outlive the put call.
+pub fn pn_bytes(bytes: &[u8]) -> proton_sys::pn_bytes_t {
+    proton_sys::pn_bytes_t {
+        size: bytes.len() as proton_sys::size_t,
+        start: bytes.as_ptr() as *const ::libc::c_char
+    }
+}
 
 pub struct Encoder<'e> {
     data: *mut proton_sys::pn_data_t,
+    // set by emit_enum when the enum is AMQP_TYPE
+    typed: bool,
+    // AMQP type the next emitted value has to be put as
+    hint: Option<proton_sys::pn_type_t>,
+    // bytes of the binary, uuid or decimal128 being emitted as a sequence
+    bytes: Option<Vec<u8>>,
     __phantom: PhantomData<&'e ()>
 }
 
@@ -95,10 +154,122 @@ impl<'e> Encoder<'e> {
 
     fn with_capacity(capacity: ::libc::size_t) -> Encoder<'e> {
         let data = unsafe{proton_sys::pn_data(capacity)};
-        Encoder {data: data, __phantom: PhantomData}
+        Encoder {data: data, typed: false, hint: None, bytes: None, __phantom: PhantomData}
+    }
+
+    /// Returns the bytes collected in place of the elements of a binary,
+    /// uuid or decimal128. Anything else emitted as an element is an error.
+    fn take_bytes(&mut self) -> Result<Vec<u8>, EncoderError> {
+        match self.bytes.take() {
+            Some(bytes) if unsafe{proton_sys::pn_data_size(self.data)} == 0 => Ok(bytes),
+            _ => Err(EncoderError::DataError(proton_sys::PN_ARG_ERR as i32))
+        }
+    }
+
+    /// Puts a list and emits whatever `f` emits as its elements.
+    fn emit_list<F>(&mut self, f: F) -> EncodeResult where
+        F: FnOnce(&mut Encoder<'e>) -> EncodeResult,
+    {
+        unsafe {
+            proton_sys::pn_data_put_list(self.data);
+            proton_sys::pn_data_enter(self.data);
+        }
+        let result = f(self);
+        unsafe {proton_sys::pn_data_exit(self.data)};
+        result
+    }
+
+    /// Emits a variant of the AMQP_TYPE enum as the AMQP type it names.
+    fn emit_typed<F>(&mut self, name: &str, f: F) -> EncodeResult where
+        F: FnOnce(&mut Encoder<'e>) -> EncodeResult,
+    {
+        let result = match type_for_variant(name) {
+            Some(proton_sys::PN_NULL) => serialize::Encoder::emit_nil(self),
+            Some(proton_sys::PN_DESCRIBED) => {
+                // the descriptor and the value are the variant arguments
+                unsafe {
+                    proton_sys::pn_data_put_described(self.data);
+                    proton_sys::pn_data_enter(self.data);
+                }
+                let result = f(self);
+                unsafe {proton_sys::pn_data_exit(self.data)};
+                result
+            },
+            Some(t) => {
+                self.hint = Some(t);
+                f(self)
+            },
+            None => Err(EncoderError::DataError(proton_sys::PN_ARG_ERR as i32))
+        };
+        self.hint = None;
+        result
+    }
+}
+
+impl<'e> Drop for Encoder<'e> {
+    fn drop(&mut self) {
+        unsafe {proton_sys::pn_data_free(self.data)};
     }
 }
 
+/// Copies `bytes` into the 16 bytes of a uuid or a decimal128.
+fn ubytes16(bytes: &[u8]) -> Result<[::libc::c_char; 16], EncoderError> {
+    if bytes.len() != 16 {
+        return Err(EncoderError::DataError(proton_sys::PN_ARG_ERR as i32));
+    }
+
+    let mut array = [0; 16];
+    for (dst, src) in array.iter_mut().zip(bytes.iter()) {
+        *dst = *src as ::libc::c_char;
+    }
+    Ok(array)
+}
+
+/// Returns the type shared by all the top level values in `data` if they
+/// can be the elements of an AMQP array, that is, if there's at least one
+/// of them and they're all of the same scalar type.
+fn array_type(data: *mut proton_sys::pn_data_t) -> Option<proton_sys::pn_type_t> {
+    let mut array_type = None;
+
+    unsafe {
+        proton_sys::pn_data_rewind(data);
+        while proton_sys::pn_data_next(data) != 0 {
+            let t = proton_sys::pn_data_type(data);
+            match t {
+                proton_sys::PN_NULL | proton_sys::PN_DESCRIBED | proton_sys::PN_ARRAY |
+                proton_sys::PN_LIST | proton_sys::PN_MAP => return None,
+                _ => {}
+            }
+
+            match array_type {
+                Some(prev) if prev != t => return None,
+                _ => array_type = Some(t)
+            }
+        }
+    }
+
+    array_type
+}
+
+/// Reads the top level values in `data` back as a described array if
+/// they're all described values. Putting the array checks they share a
+/// descriptor and a type.
+fn described_array(data: *mut proton_sys::pn_data_t) -> Option<Value> {
+    let mut elements = Vec::new();
+
+    unsafe {
+        proton_sys::pn_data_rewind(data);
+        while proton_sys::pn_data_next(data) != 0 {
+            match Value::get(data) {
+                Ok(element @ Value::Described(..)) => elements.push(element),
+                _ => return None
+            }
+        }
+    }
+
+    if elements.is_empty() {None} else {Some(Value::Array(elements))}
+}
+
 impl<'a> serialize::Encoder for Encoder<'a> {
     type Error = EncoderError;
 
@@ -107,28 +278,45 @@ impl<'a> serialize::Encoder for Encoder<'a> {
     }
 
     fn emit_usize(&mut self, v: usize) -> EncodeResult {
-        Ok(unsafe{proton_sys::pn_data_put_uint(&mut *self.data, v as u32);})
+        Ok(unsafe{proton_sys::pn_data_put_ulong(&mut *self.data, v as u64);})
     }
     fn emit_u64(&mut self, v: u64) -> EncodeResult {
-        // NOTE(flaper87): check word size
-        Ok(unsafe{proton_sys::pn_data_put_ulong(&mut *self.data, v);})
+        match self.hint.take() {
+            Some(proton_sys::PN_DECIMAL64) => {
+                Ok(unsafe{proton_sys::pn_data_put_decimal64(&mut *self.data, v);})
+            },
+            _ => Ok(unsafe{proton_sys::pn_data_put_ulong(&mut *self.data, v);})
+        }
     }
     fn emit_u32(&mut self, v: u32) -> EncodeResult {
-        Ok(unsafe{proton_sys::pn_data_put_uint(&mut *self.data, v);})
+        match self.hint.take() {
+            Some(proton_sys::PN_DECIMAL32) => {
+                Ok(unsafe{proton_sys::pn_data_put_decimal32(&mut *self.data, v);})
+            },
+            _ => Ok(unsafe{proton_sys::pn_data_put_uint(&mut *self.data, v);})
+        }
     }
     fn emit_u16(&mut self, v: u16) -> EncodeResult {
         Ok(unsafe{proton_sys::pn_data_put_ushort(&mut *self.data, v);})
     }
     fn emit_u8(&mut self, v: u8) -> EncodeResult {
+        if let Some(ref mut bytes) = self.bytes {
+            bytes.push(v);
+            return Ok(());
+        }
         Ok(unsafe{proton_sys::pn_data_put_ubyte(&mut *self.data, v);})
     }
 
     fn emit_isize(&mut self, v: isize) -> EncodeResult {
-        // NOTE(flaper87): check word size
-        Ok(unsafe{proton_sys::pn_data_put_int(&mut *self.data, v as i32);})
+        Ok(unsafe{proton_sys::pn_data_put_long(&mut *self.data, v as i64);})
     }
     fn emit_i64(&mut self, v: i64) -> EncodeResult {
-        Ok(unsafe{proton_sys::pn_data_put_long(&mut *self.data, v);})
+        match self.hint.take() {
+            Some(proton_sys::PN_TIMESTAMP) => {
+                Ok(unsafe{proton_sys::pn_data_put_timestamp(&mut *self.data, v);})
+            },
+            _ => Ok(unsafe{proton_sys::pn_data_put_long(&mut *self.data, v);})
+        }
     }
     fn emit_i32(&mut self, v: i32) -> EncodeResult {
         Ok(unsafe{proton_sys::pn_data_put_int(&mut *self.data, v);})
@@ -145,7 +333,7 @@ impl<'a> serialize::Encoder for Encoder<'a> {
     }
 
     fn emit_f64(&mut self, v: f64) -> EncodeResult {
-        Ok(unsafe{proton_sys::pn_data_put_float(&mut *self.data, v as f32);})
+        Ok(unsafe{proton_sys::pn_data_put_double(&mut *self.data, v);})
     }
     fn emit_f32(&mut self, v: f32) -> EncodeResult {
         Ok(unsafe{proton_sys::pn_data_put_float(&mut *self.data, v);})
@@ -156,15 +344,19 @@ impl<'a> serialize::Encoder for Encoder<'a> {
     }
 
     fn emit_str(&mut self, slice: &str) -> EncodeResult {
-        Ok(unsafe{
-            let s = CString::new(slice).unwrap();
-            let bytes = proton_sys::pn_bytes(slice.len() as proton_sys::size_t, s.as_ptr());
-            proton_sys::pn_data_put_string(&mut *self.data, bytes);})
+        let bytes = pn_bytes(slice.as_bytes());
+        match self.hint.take() {
+            Some(proton_sys::PN_SYMBOL) => {
+                Ok(unsafe{proton_sys::pn_data_put_symbol(&mut *self.data, bytes);})
+            },
+            _ => Ok(unsafe{proton_sys::pn_data_put_string(&mut *self.data, bytes);})
+        }
     }
 
-    fn emit_enum<F>(&mut self, _name: &str, f: F) -> EncodeResult where
+    fn emit_enum<F>(&mut self, name: &str, f: F) -> EncodeResult where
         F: FnOnce(&mut Encoder<'a>) -> EncodeResult,
     {
+        self.typed = name == AMQP_TYPE;
         f(self)
     }
 
@@ -175,7 +367,19 @@ impl<'a> serialize::Encoder for Encoder<'a> {
                             f: F) -> EncodeResult where
         F: FnOnce(&mut Encoder<'a>) -> EncodeResult,
     {
-        Ok(())
+        if self.typed {
+            self.typed = false;
+            return self.emit_typed(name, f);
+        }
+
+        if cnt == 0 {
+            return self.emit_str(name);
+        }
+
+        self.emit_list(|e| {
+            try!(e.emit_str(name));
+            f(e)
+        })
     }
 
     fn emit_enum_variant_arg<F>(&mut self, idx: usize, f: F) -> EncodeResult where
@@ -206,7 +410,7 @@ impl<'a> serialize::Encoder for Encoder<'a> {
     fn emit_struct<F>(&mut self, _: &str, _: usize, f: F) -> EncodeResult where
         F: FnOnce(&mut Encoder<'a>) -> EncodeResult,
     {
-        Ok(())
+        self.emit_list(f)
     }
 
     fn emit_struct_field<F>(&mut self, name: &str, idx: usize, f: F) -> EncodeResult where
@@ -215,10 +419,10 @@ impl<'a> serialize::Encoder for Encoder<'a> {
         f(self)
     }
 
-    fn emit_tuple<F>(&mut self, len: usize, f: F) -> EncodeResult where
+    fn emit_tuple<F>(&mut self, _len: usize, f: F) -> EncodeResult where
         F: FnOnce(&mut Encoder<'a>) -> EncodeResult,
     {
-        self.emit_seq(len, f)
+        self.emit_list(f)
     }
     fn emit_tuple_arg<F>(&mut self, idx: usize, f: F) -> EncodeResult where
         F: FnOnce(&mut Encoder<'a>) -> EncodeResult,
@@ -229,7 +433,7 @@ impl<'a> serialize::Encoder for Encoder<'a> {
     fn emit_tuple_struct<F>(&mut self, _name: &str, len: usize, f: F) -> EncodeResult where
         F: FnOnce(&mut Encoder<'a>) -> EncodeResult,
     {
-        self.emit_seq(len, f)
+        self.emit_tuple(len, f)
     }
     fn emit_tuple_struct_arg<F>(&mut self, idx: usize, f: F) -> EncodeResult where
         F: FnOnce(&mut Encoder<'a>) -> EncodeResult,
@@ -254,6 +458,66 @@ impl<'a> serialize::Encoder for Encoder<'a> {
     fn emit_seq<F>(&mut self, _len: usize, f: F) -> EncodeResult where
         F: FnOnce(&mut Encoder<'a>) -> EncodeResult,
     {
+        // The element types are only known once they've been emitted, so
+        // they go to a scratch encoder first and are then appended either
+        // to an array, if they all share the same scalar type, or a list.
+        let hint = self.hint.take();
+        let mut elements = Encoder::new();
+        match hint {
+            Some(proton_sys::PN_BINARY) | Some(proton_sys::PN_UUID) | Some(proton_sys::PN_DECIMAL128) => {
+                // the elements are the bytes, collected rather than put one by one
+                elements.bytes = Some(Vec::new());
+            },
+            _ => {}
+        }
+        try!(f(&mut elements));
+
+        unsafe {
+            match hint {
+                Some(proton_sys::PN_BINARY) => {
+                    let bytes = try!(elements.take_bytes());
+                    proton_sys::pn_data_put_binary(self.data, pn_bytes(&bytes));
+                    return Ok(());
+                },
+                Some(proton_sys::PN_UUID) => {
+                    let bytes = try!(ubytes16(&try!(elements.take_bytes())));
+                    proton_sys::pn_data_put_uuid(self.data, proton_sys::pn_uuid_t {bytes: bytes});
+                    return Ok(());
+                },
+                Some(proton_sys::PN_DECIMAL128) => {
+                    let bytes = try!(ubytes16(&try!(elements.take_bytes())));
+                    proton_sys::pn_data_put_decimal128(self.data,
+                                                       proton_sys::pn_decimal128_t {bytes: bytes});
+                    return Ok(());
+                },
+                Some(proton_sys::PN_LIST) => {
+                    proton_sys::pn_data_put_list(self.data);
+                },
+                Some(proton_sys::PN_ARRAY) => {
+                    let t = match array_type(elements.data) {
+                        Some(t) => t,
+                        None if proton_sys::pn_data_size(elements.data) == 0 => proton_sys::PN_NULL,
+                        None => match described_array(elements.data) {
+                            Some(array) => {
+                                return array.put(self.data)
+                                    .map_err(|_| EncoderError::DataError(proton_sys::PN_ARG_ERR as i32));
+                            },
+                            None => return Err(EncoderError::DataError(proton_sys::PN_ARG_ERR as i32))
+                        }
+                    };
+                    proton_sys::pn_data_put_array(self.data, 0, t);
+                },
+                _ => {
+                    match array_type(elements.data) {
+                        Some(t) => proton_sys::pn_data_put_array(self.data, 0, t),
+                        None => proton_sys::pn_data_put_list(self.data)
+                    };
+                }
+            }
+            proton_sys::pn_data_enter(self.data);
+            proton_sys::pn_data_append(self.data, elements.data);
+            proton_sys::pn_data_exit(self.data);
+        }
         Ok(())
     }
 
@@ -266,13 +530,19 @@ impl<'a> serialize::Encoder for Encoder<'a> {
     fn emit_map<F>(&mut self, _len: usize, f: F) -> EncodeResult where
         F: FnOnce(&mut Encoder<'a>) -> EncodeResult,
     {
-        Ok(())
+        unsafe {
+            proton_sys::pn_data_put_map(self.data);
+            proton_sys::pn_data_enter(self.data);
+        }
+        let result = f(self);
+        unsafe {proton_sys::pn_data_exit(self.data)};
+        result
     }
 
     fn emit_map_elt_key<F>(&mut self, idx: usize, f: F) -> EncodeResult where
         F: FnOnce(&mut Encoder<'a>) -> EncodeResult,
     {
-        Ok(())
+        f(self)
     }
 
     fn emit_map_elt_val<F>(&mut self, _idx: usize, f: F) -> EncodeResult where
@@ -282,44 +552,87 @@ impl<'a> serialize::Encoder for Encoder<'a> {
     }
 }
 
-/// Shortcut function to encode a `T` into a JSON `String`
-pub fn encode<T: serialize::Encodable>(object: &T) -> Result<Vec<i8>, EncoderError> {
+/// Shortcut function to encode a `T` into AMQP encoded bytes
+pub fn encode<T: serialize::Encodable>(object: &T) -> Result<Vec<u8>, EncoderError> {
     let mut encoder = Encoder::new();
     try!(object.encode(&mut encoder));
     let mut size = 1024;
-    let mut bytes = Vec::with_capacity(size);
-    let mut result = proton_sys::PN_OVERFLOW;
 
-    while result == proton_sys::PN_OVERFLOW {
-        result = unsafe{proton_sys::pn_data_encode(encoder.data,
-                                                   bytes.as_mut_ptr(),
-                                                   size as u64) as i8};
-
-        if result > 0 {
+    loop {
+        let mut bytes: Vec<u8> = Vec::with_capacity(size);
+        let result = unsafe{proton_sys::pn_data_encode(encoder.data,
+                                                       bytes.as_mut_ptr() as *mut ::libc::c_char,
+                                                       size as proton_sys::size_t)};
+
+        if result == proton_sys::PN_OVERFLOW as proton_sys::ssize_t {
+            size *= 2;
+        } else if result < 0 {
+            return Err(EncoderError::DataError(result as i32));
+        } else {
             unsafe {bytes.set_len(result as usize);}
-            break;
-        } else if result <= 0 {
-            // not ok
-            println!("ERROR");
-            break;
+            return Ok(bytes);
         }
-
-        size *= 2;
-        bytes = Vec::with_capacity(size);
     }
+}
+
+/// Encodes `object` and appends it to `data`, after the data's current
+/// position.
+pub fn encode_data<T: serialize::Encodable>(object: &T,
+                                            data: *mut proton_sys::pn_data_t) -> EncodeResult {
+    let mut encoder = Encoder::new();
+    try!(object.encode(&mut encoder));
 
-    bytes.shrink_to_fit();
-    Ok(bytes)
+    match unsafe{proton_sys::pn_data_append(data, encoder.data)} {
+        0 => Ok(()),
+        err => Err(EncoderError::DataError(err as i32))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::decoder::decode;
+    use std::collections::HashMap;
     use std::ffi::CStr;
     use std::slice;
     use std::str;
     use proton_sys;
 
+    #[derive(RustcEncodable, RustcDecodable, PartialEq, Debug)]
+    struct Order {
+        id: u64,
+        items: Vec<String>,
+        note: Option<String>,
+    }
+
+    #[derive(RustcEncodable, RustcDecodable, PartialEq, Debug)]
+    enum Shape {
+        Point,
+        Circle(f64),
+        Rect {w: u32, h: u32},
+    }
+
+    /// Data decoded by `decode_data`, freed when dropped.
+    struct Decoded(*mut proton_sys::pn_data_t);
+
+    impl Drop for Decoded {
+        fn drop(&mut self) {
+            unsafe {proton_sys::pn_data_free(self.0)};
+        }
+    }
+
+    fn decode_data(encoded: &[u8]) -> Decoded {
+        unsafe {
+            let data = proton_sys::pn_data(16);
+            proton_sys::pn_data_decode(data,
+                                       encoded.as_ptr() as *const ::libc::c_char,
+                                       encoded.len() as u64);
+            proton_sys::pn_data_rewind(data);
+            proton_sys::pn_data_next(data);
+            Decoded(data)
+        }
+    }
+
     macro_rules! create_test {
         ($func:ident, $get_data:ident, $value:expr) => (
             #[test]
@@ -330,7 +643,7 @@ mod tests {
                 unsafe {
                     data = proton_sys::pn_data(1024);
                     let err = proton_sys::pn_data_decode(data,
-                                                         encoded.as_ptr(),
+                                                         encoded.as_ptr() as *const ::libc::c_char,
                                                          encoded.len() as u64);
                 }
 
@@ -343,6 +656,7 @@ mod tests {
                     proton_sys::$get_data(data)
                 };
                 assert_eq!(value, data_content);
+                unsafe {proton_sys::pn_data_free(data)};
             }
         )
     }
@@ -363,7 +677,7 @@ mod tests {
         unsafe {
             data = proton_sys::pn_data(1024);
             let err = proton_sys::pn_data_decode(data,
-                                                 encoded.as_ptr(),
+                                                 encoded.as_ptr() as *const ::libc::c_char,
                                                  encoded.len() as u64);
         }
 
@@ -391,4 +705,97 @@ mod tests {
     create_test!(long, pn_data_get_long, 1i64);
 
     create_test!(float, pn_data_get_float, 1f32);
+
+    #[test]
+    fn test_homogeneous_seq_encoding() {
+        let value = vec![1i32, 2, 3];
+        let encoded = encode(&value).unwrap();
+        let decoded = decode_data(&encoded);
+        let data = decoded.0;
+
+        assert_eq!("PN_ARRAY", get_type_name(data));
+        assert_eq!(3, unsafe{proton_sys::pn_data_get_array(data)});
+        assert_eq!(proton_sys::PN_INT, unsafe{proton_sys::pn_data_get_array_type(data)});
+        assert_eq!(value, decode::<Vec<i32>>(&encoded).unwrap());
+    }
+
+    #[test]
+    fn test_mixed_seq_encoding() {
+        let value = vec![Some(1u32), None, Some(3)];
+        let encoded = encode(&value).unwrap();
+        let decoded = decode_data(&encoded);
+        let data = decoded.0;
+
+        assert_eq!("PN_LIST", get_type_name(data));
+        assert_eq!(3, unsafe{proton_sys::pn_data_get_list(data)});
+        assert_eq!(value, decode::<Vec<Option<u32>>>(&encoded).unwrap());
+    }
+
+    #[test]
+    fn test_nested_seq_encoding() {
+        let value = vec![vec!["a".to_string()], vec![], vec!["b".to_string(), "c".to_string()]];
+        let encoded = encode(&value).unwrap();
+        assert_eq!("PN_LIST", get_type_name(decode_data(&encoded).0));
+        assert_eq!(value, decode::<Vec<Vec<String>>>(&encoded).unwrap());
+    }
+
+    #[test]
+    fn test_map_encoding() {
+        let mut value = HashMap::new();
+        value.insert("one".to_string(), vec![1u64]);
+        value.insert("two".to_string(), vec![1u64, 2]);
+        let encoded = encode(&value).unwrap();
+        let decoded = decode_data(&encoded);
+        let data = decoded.0;
+
+        assert_eq!("PN_MAP", get_type_name(data));
+        assert_eq!(4, unsafe{proton_sys::pn_data_get_map(data)});
+        assert_eq!(value, decode::<HashMap<String, Vec<u64>>>(&encoded).unwrap());
+    }
+
+    #[test]
+    fn test_tuple_encoding() {
+        let value = (1u8, "two".to_string(), 3.5f64);
+        let encoded = encode(&value).unwrap();
+
+        assert_eq!("PN_LIST", get_type_name(decode_data(&encoded).0));
+        assert_eq!(value, decode::<(u8, String, f64)>(&encoded).unwrap());
+    }
+
+    #[test]
+    fn test_struct_encoding() {
+        let value = Order {
+            id: 42,
+            items: vec!["apples".to_string(), "pears".to_string()],
+            note: None,
+        };
+        let encoded = encode(&value).unwrap();
+        let decoded = decode_data(&encoded);
+        let data = decoded.0;
+
+        assert_eq!("PN_LIST", get_type_name(data));
+        assert_eq!(3, unsafe{proton_sys::pn_data_get_list(data)});
+        assert_eq!(value, decode::<Order>(&encoded).unwrap());
+    }
+
+    #[test]
+    fn test_enum_encoding() {
+        for value in vec![Shape::Point, Shape::Circle(1.5), Shape::Rect {w: 2, h: 3}] {
+            let encoded = encode(&value).unwrap();
+            assert_eq!(value, decode::<Shape>(&encoded).unwrap());
+        }
+
+        let encoded = encode(&Shape::Point).unwrap();
+        assert_eq!("PN_STRING", get_type_name(decode_data(&encoded).0));
+        let encoded = encode(&Shape::Circle(1.5)).unwrap();
+        assert_eq!("PN_LIST", get_type_name(decode_data(&encoded).0));
+    }
+
+    #[test]
+    fn test_large_value_encoding() {
+        let value: Vec<String> = (0..512).map(|i| format!("item-{}", i)).collect();
+        let encoded = encode(&value).unwrap();
+        assert!(encoded.len() > 1024);
+        assert_eq!(value, decode::<Vec<String>>(&encoded).unwrap());
+    }
 }
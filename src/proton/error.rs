@@ -0,0 +1,157 @@
+use std::{error, fmt, i8, result, str};
+use std::ffi::CStr;
+use proton_sys;
+
+use super::Condition;
+
+/// Kind of failure, after proton's `PN_*` error codes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    /// end of stream
+    Eos,
+    /// generic failure
+    Failed,
+    /// a buffer was too small
+    Overflow,
+    /// the input ended too soon
+    Underflow,
+    /// the operation isn't allowed in the current state
+    State,
+    /// invalid argument
+    Argument,
+    Timeout,
+    Interrupted,
+    InProgress,
+    /// any other code
+    Other(i32),
+}
+
+impl ErrorKind {
+    pub fn from_code(code: i32) -> ErrorKind {
+        if code < i8::MIN as i32 || code > i8::MAX as i32 {
+            return ErrorKind::Other(code);
+        }
+
+        match code as i8 {
+            proton_sys::PN_EOS => ErrorKind::Eos,
+            proton_sys::PN_ERR => ErrorKind::Failed,
+            proton_sys::PN_OVERFLOW => ErrorKind::Overflow,
+            proton_sys::PN_UNDERFLOW => ErrorKind::Underflow,
+            proton_sys::PN_STATE_ERR => ErrorKind::State,
+            proton_sys::PN_ARG_ERR => ErrorKind::Argument,
+            proton_sys::PN_TIMEOUT => ErrorKind::Timeout,
+            proton_sys::PN_INTR => ErrorKind::Interrupted,
+            proton_sys::PN_INPROGRESS => ErrorKind::InProgress,
+            _ => ErrorKind::Other(code),
+        }
+    }
+}
+
+/// An error reported by proton: its code and, when proton has one, the
+/// text describing what went wrong.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    description: String,
+    condition: Option<Condition>,
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+unsafe fn c_string(ptr: *const ::libc::c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+
+    match str::from_utf8(CStr::from_ptr(ptr).to_bytes()) {
+        Ok(s) if !s.is_empty() => Some(s.to_string()),
+        _ => None
+    }
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, description: &str) -> Error {
+        Error {kind: kind, description: description.to_string(), condition: None}
+    }
+
+    /// Error for a `PN_*` code, described by the code's name.
+    pub fn from_code(code: i32) -> Error {
+        let description = unsafe {c_string(proton_sys::pn_code(code))};
+        Error {
+            kind: ErrorKind::from_code(code),
+            description: description.unwrap_or_else(|| format!("error {}", code)),
+            condition: None,
+        }
+    }
+
+    /// Error for a `PN_*` code returned by an object that keeps the details
+    /// in `error`, such as a message or a transport.
+    pub fn from_pn_error(code: i32, error: *mut proton_sys::pn_error_t) -> Error {
+        let text = if error.is_null() {
+            None
+        } else {
+            unsafe {c_string(proton_sys::pn_error_text(error))}
+        };
+
+        match text {
+            Some(text) => Error::new(ErrorKind::from_code(code), &text),
+            None => Error::from_code(code)
+        }
+    }
+
+    /// Error for a `PN_*` code returned by a transport. When the transport
+    /// failed because of the peer, e.g. with a framing error, its condition
+    /// says why.
+    pub fn from_transport(code: i32, transport: *mut proton_sys::pn_transport_t) -> Error {
+        let condition = Condition::from_ptr(unsafe {proton_sys::pn_transport_condition(transport)});
+        match condition {
+            Some(condition) => {
+                let description = match condition.description {
+                    Some(ref description) => format!("{}: {}", condition.name, description),
+                    None => condition.name.clone()
+                };
+                Error {
+                    kind: ErrorKind::from_code(code),
+                    description: description,
+                    condition: Some(condition),
+                }
+            },
+            None => Error::from_pn_error(code, unsafe {proton_sys::pn_transport_error(transport)})
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Condition the error came with, if any.
+    pub fn condition(&self) -> Option<&Condition> {
+        self.condition.as_ref()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.description)
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proton_sys;
+
+    #[test]
+    fn test_from_code() {
+        let error = Error::from_code(proton_sys::PN_OVERFLOW as i32);
+        assert_eq!(ErrorKind::Overflow, error.kind());
+        assert_eq!("PN_OVERFLOW", error.to_string());
+        assert_eq!(ErrorKind::Other(-42), Error::from_code(-42).kind());
+    }
+}
@@ -0,0 +1,424 @@
+use std::ffi::CStr;
+use std::str;
+use rustc_serialize::{Encodable, Decodable};
+use proton_sys;
+
+use super::c_string;
+use super::encoder::{self, EncodeResult, pn_bytes};
+use super::decoder::{Decoder, DecodeResult, bytes_as_slice};
+use super::error::Error;
+use super::data::{DataMap, DataMapMut, AnnotationKey};
+use super::types::Timestamp;
+use super::value::Value;
+
+/// An AMQP message: its header, properties and body.
+pub struct Message {
+    ptr: *mut proton_sys::pn_message_t
+}
+
+unsafe fn to_str<'a>(ptr: *const ::libc::c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        None
+    } else {
+        str::from_utf8(CStr::from_ptr(ptr).to_bytes()).ok()
+    }
+}
+
+// Getter and setter for a string field of the message.
+macro_rules! str_field {
+    ($(#[$attr:meta])* $name:ident, $set_name:ident, $get:ident, $set:ident) => (
+        impl Message {
+            $(#[$attr])*
+            pub fn $name(&self) -> Option<&str> {
+                unsafe {to_str(proton_sys::$get(self.ptr))}
+            }
+
+            /// Fails with `ErrorKind::Argument` if `value` has a nul byte in it.
+            pub fn $set_name(&mut self, value: Option<&str>) -> Result<(), Error> {
+                let value = match value {
+                    Some(value) => Some(try!(c_string(value))),
+                    None => None
+                };
+                let ptr = value.as_ref().map_or(::std::ptr::null(), |v| v.as_ptr());
+                unsafe {proton_sys::$set(self.ptr, ptr);}
+                Ok(())
+            }
+        }
+    )
+}
+
+impl Message {
+    pub fn new() -> Message {
+        let message = unsafe {proton_sys::pn_message()};
+        Message {
+            ptr: message
+        }
+    }
+
+    /// Resets every field of the message and empties its body.
+    pub fn clear(&mut self) {
+        unsafe {proton_sys::pn_message_clear(self.ptr)};
+    }
+
+    pub fn durable(&self) -> bool {
+        unsafe {proton_sys::pn_message_is_durable(self.ptr) != 0}
+    }
+
+    pub fn set_durable(&mut self, durable: bool) {
+        unsafe {proton_sys::pn_message_set_durable(self.ptr, durable as u8);}
+    }
+
+    pub fn priority(&self) -> u8 {
+        unsafe {proton_sys::pn_message_get_priority(self.ptr)}
+    }
+
+    pub fn set_priority(&mut self, priority: u8) {
+        unsafe {proton_sys::pn_message_set_priority(self.ptr, priority);}
+    }
+
+    /// Time to live, in milliseconds.
+    pub fn ttl(&self) -> u32 {
+        unsafe {proton_sys::pn_message_get_ttl(self.ptr)}
+    }
+
+    pub fn set_ttl(&mut self, ttl: u32) {
+        unsafe {proton_sys::pn_message_set_ttl(self.ptr, ttl);}
+    }
+
+    pub fn first_acquirer(&self) -> bool {
+        unsafe {proton_sys::pn_message_is_first_acquirer(self.ptr) != 0}
+    }
+
+    pub fn set_first_acquirer(&mut self, first: bool) {
+        unsafe {proton_sys::pn_message_set_first_acquirer(self.ptr, first as u8);}
+    }
+
+    pub fn delivery_count(&self) -> u32 {
+        unsafe {proton_sys::pn_message_get_delivery_count(self.ptr)}
+    }
+
+    pub fn set_delivery_count(&mut self, count: u32) {
+        unsafe {proton_sys::pn_message_set_delivery_count(self.ptr, count);}
+    }
+
+    /// The message id: a ulong, uuid, binary or string.
+    pub fn id(&self) -> DecodeResult<Option<Value>> {
+        Value::from_data(unsafe{proton_sys::pn_message_id(self.ptr)})
+    }
+
+    pub fn set_id(&mut self, id: Option<&Value>) -> DecodeResult<()> {
+        Value::set_data(unsafe{proton_sys::pn_message_id(self.ptr)}, id)
+    }
+
+    pub fn user_id(&self) -> &[u8] {
+        unsafe {bytes_as_slice(proton_sys::pn_message_get_user_id(self.ptr))}
+    }
+
+    pub fn set_user_id(&mut self, user_id: &[u8]) {
+        unsafe {proton_sys::pn_message_set_user_id(self.ptr, pn_bytes(user_id));}
+    }
+
+    /// Id of the message this one relates to, usually a request's id on
+    /// its reply.
+    pub fn correlation_id(&self) -> DecodeResult<Option<Value>> {
+        Value::from_data(unsafe{proton_sys::pn_message_correlation_id(self.ptr)})
+    }
+
+    pub fn set_correlation_id(&mut self, id: Option<&Value>) -> DecodeResult<()> {
+        Value::set_data(unsafe{proton_sys::pn_message_correlation_id(self.ptr)}, id)
+    }
+
+    pub fn expiry_time(&self) -> Timestamp {
+        Timestamp(unsafe {proton_sys::pn_message_get_expiry_time(self.ptr)})
+    }
+
+    pub fn set_expiry_time(&mut self, time: Timestamp) {
+        unsafe {proton_sys::pn_message_set_expiry_time(self.ptr, time.0);}
+    }
+
+    pub fn creation_time(&self) -> Timestamp {
+        Timestamp(unsafe {proton_sys::pn_message_get_creation_time(self.ptr)})
+    }
+
+    pub fn set_creation_time(&mut self, time: Timestamp) {
+        unsafe {proton_sys::pn_message_set_creation_time(self.ptr, time.0);}
+    }
+
+    pub fn group_sequence(&self) -> i32 {
+        unsafe {proton_sys::pn_message_get_group_sequence(self.ptr)}
+    }
+
+    pub fn set_group_sequence(&mut self, sequence: i32) {
+        unsafe {proton_sys::pn_message_set_group_sequence(self.ptr, sequence);}
+    }
+
+    /// Whether the body is sent as is rather than as an `amqp-value`
+    /// section: binary bodies go out as a data section and list bodies as
+    /// amqp-sequence sections.
+    pub fn inferred(&self) -> bool {
+        unsafe {proton_sys::pn_message_is_inferred(self.ptr) != 0}
+    }
+
+    pub fn set_inferred(&mut self, inferred: bool) {
+        unsafe {proton_sys::pn_message_set_inferred(self.ptr, inferred as u8);}
+    }
+
+    /// Application properties, used for filtering and routing.
+    pub fn properties(&self) -> DataMap<String> {
+        unsafe {DataMap::new(proton_sys::pn_message_properties(self.ptr))}
+    }
+
+    pub fn properties_mut(&mut self) -> DataMapMut<String> {
+        unsafe {DataMapMut::new(proton_sys::pn_message_properties(self.ptr))}
+    }
+
+    /// Message annotations, for the infrastructure along the message's
+    /// path. They travel with the message to its destination.
+    pub fn annotations(&self) -> DataMap<AnnotationKey> {
+        unsafe {DataMap::new(proton_sys::pn_message_annotations(self.ptr))}
+    }
+
+    pub fn annotations_mut(&mut self) -> DataMapMut<AnnotationKey> {
+        unsafe {DataMapMut::new(proton_sys::pn_message_annotations(self.ptr))}
+    }
+
+    /// Delivery annotations, for the next hop only.
+    pub fn instructions(&self) -> DataMap<AnnotationKey> {
+        unsafe {DataMap::new(proton_sys::pn_message_instructions(self.ptr))}
+    }
+
+    pub fn instructions_mut(&mut self) -> DataMapMut<AnnotationKey> {
+        unsafe {DataMapMut::new(proton_sys::pn_message_instructions(self.ptr))}
+    }
+
+    /// Encodes the whole message, header and body sections included, in
+    /// the format it's sent in over a link.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let mut size = 1024;
+
+        loop {
+            let mut bytes: Vec<u8> = Vec::with_capacity(size);
+            let mut len = size as proton_sys::size_t;
+            let result = unsafe {
+                proton_sys::pn_message_encode(self.ptr,
+                                              bytes.as_mut_ptr() as *mut ::libc::c_char,
+                                              &mut len)
+            };
+
+            if result == proton_sys::PN_OVERFLOW as i32 {
+                size *= 2;
+            } else if result < 0 {
+                return Err(self.error(result));
+            } else {
+                unsafe {bytes.set_len(len as usize);}
+                return Ok(bytes);
+            }
+        }
+    }
+
+    /// Decodes a message encoded by `encode`, or received over a link.
+    pub fn decode(bytes: &[u8]) -> Result<Message, Error> {
+        let message = Message::new();
+        let result = unsafe {
+            proton_sys::pn_message_decode(message.ptr,
+                                          bytes.as_ptr() as *const ::libc::c_char,
+                                          bytes.len() as proton_sys::size_t)
+        };
+
+        if result < 0 {
+            return Err(message.error(result));
+        }
+        Ok(message)
+    }
+
+    fn error(&self, code: i32) -> Error {
+        Error::from_pn_error(code, unsafe{proton_sys::pn_message_error(self.ptr)})
+    }
+
+    /// Decodes the body as a `T`.
+    pub fn body<T: Decodable>(&self) -> DecodeResult<T> {
+        let mut decoder = Decoder::from_data(unsafe{proton_sys::pn_message_body(self.ptr)});
+        Decodable::decode(&mut decoder)
+    }
+
+    /// Replaces the body with `body`.
+    pub fn set_body<T: Encodable>(&mut self, body: &T) -> EncodeResult {
+        let data = unsafe{proton_sys::pn_message_body(self.ptr)};
+        unsafe {proton_sys::pn_data_clear(data)};
+        encoder::encode_data(body, data)
+    }
+}
+
+str_field!(address, set_address, pn_message_get_address, pn_message_set_address);
+str_field!(subject, set_subject, pn_message_get_subject, pn_message_set_subject);
+str_field!(reply_to, set_reply_to, pn_message_get_reply_to, pn_message_set_reply_to);
+str_field!(#[doc = "MIME type of the body."]
+           content_type, set_content_type, pn_message_get_content_type, pn_message_set_content_type);
+str_field!(content_encoding, set_content_encoding,
+           pn_message_get_content_encoding, pn_message_set_content_encoding);
+str_field!(group_id, set_group_id, pn_message_get_group_id, pn_message_set_group_id);
+str_field!(reply_to_group_id, set_reply_to_group_id,
+           pn_message_get_reply_to_group_id, pn_message_set_reply_to_group_id);
+
+impl Drop for Message {
+    fn drop(&mut self) {
+        unsafe {proton_sys::pn_message_free(self.ptr)};
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::encoder::pn_bytes;
+    use super::super::error::ErrorKind;
+    use super::super::types::{Binary, Timestamp};
+    use super::super::value::Value;
+    use proton_sys;
+
+    #[derive(RustcEncodable, RustcDecodable, PartialEq, Debug)]
+    struct Order {
+        id: u64,
+        items: Vec<String>,
+    }
+
+    #[test]
+    fn test_header() {
+        let mut message = Message::new();
+        assert!(!message.durable());
+        assert_eq!(4, message.priority());
+
+        message.set_durable(true);
+        message.set_priority(9);
+        message.set_ttl(30000);
+        message.set_first_acquirer(true);
+        message.set_delivery_count(2);
+
+        assert!(message.durable());
+        assert_eq!(9, message.priority());
+        assert_eq!(30000, message.ttl());
+        assert!(message.first_acquirer());
+        assert_eq!(2, message.delivery_count());
+
+        message.clear();
+        assert!(!message.durable());
+        assert_eq!(0, message.ttl());
+    }
+
+    #[test]
+    fn test_properties() {
+        let mut message = Message::new();
+        assert_eq!(None, message.address());
+        assert_eq!(None, message.id().unwrap());
+
+        message.set_id(Some(&Value::Ulong(42))).unwrap();
+        message.set_correlation_id(Some(&Value::String("req-1".to_string()))).unwrap();
+        message.set_user_id(b"guest");
+        message.set_address(Some("queue://orders")).unwrap();
+        message.set_subject(Some("new order")).unwrap();
+        message.set_reply_to(Some("queue://replies")).unwrap();
+        message.set_content_type(Some("application/json")).unwrap();
+        message.set_content_encoding(Some("gzip")).unwrap();
+        message.set_expiry_time(Timestamp(1436000060000));
+        message.set_creation_time(Timestamp(1436000000000));
+        message.set_group_id(Some("orders")).unwrap();
+        message.set_group_sequence(3);
+        message.set_reply_to_group_id(Some("replies")).unwrap();
+
+        assert_eq!(Some(Value::Ulong(42)), message.id().unwrap());
+        assert_eq!(Some(Value::String("req-1".to_string())), message.correlation_id().unwrap());
+        assert_eq!(b"guest", message.user_id());
+        assert_eq!(Some("queue://orders"), message.address());
+        assert_eq!(Some("new order"), message.subject());
+        assert_eq!(Some("queue://replies"), message.reply_to());
+        assert_eq!(Some("application/json"), message.content_type());
+        assert_eq!(Some("gzip"), message.content_encoding());
+        assert_eq!(Timestamp(1436000060000), message.expiry_time());
+        assert_eq!(Timestamp(1436000000000), message.creation_time());
+        assert_eq!(Some("orders"), message.group_id());
+        assert_eq!(3, message.group_sequence());
+        assert_eq!(Some("replies"), message.reply_to_group_id());
+
+        let error = message.set_subject(Some("new\0order")).unwrap_err();
+        assert_eq!(ErrorKind::Argument, error.kind());
+        assert_eq!(Some("new order"), message.subject());
+
+        message.set_address(None).unwrap();
+        message.set_id(None).unwrap();
+        assert_eq!(None, message.address());
+        assert_eq!(None, message.id().unwrap());
+    }
+
+    #[test]
+    fn test_malformed_id() {
+        let message = Message::new();
+        unsafe {
+            proton_sys::pn_data_put_string(proton_sys::pn_message_id(message.ptr), pn_bytes(&[0xff]));
+        }
+        assert!(message.id().is_err());
+        assert_eq!(None, message.correlation_id().unwrap());
+    }
+
+    #[test]
+    fn test_body() {
+        let mut message = Message::new();
+        let order = Order {id: 7, items: vec!["widget".to_string(), "gadget".to_string()]};
+        message.set_body(&order).unwrap();
+        assert_eq!(order, message.body().unwrap());
+
+        message.set_body(&Binary(vec![1, 2, 3])).unwrap();
+        assert_eq!(Binary(vec![1, 2, 3]), message.body().unwrap());
+        assert_eq!(Value::Binary(vec![1, 2, 3]), message.body::<Value>().unwrap());
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let mut message = Message::new();
+        message.set_durable(true);
+        message.set_address(Some("queue://orders")).unwrap();
+        message.set_id(Some(&Value::Uuid([5; 16]))).unwrap();
+        message.set_body(&"hello".to_string()).unwrap();
+
+        let encoded = message.encode().unwrap();
+        let decoded = Message::decode(&encoded).unwrap();
+        assert!(decoded.durable());
+        assert_eq!(Some("queue://orders"), decoded.address());
+        assert_eq!(Some(Value::Uuid([5; 16])), decoded.id().unwrap());
+        assert_eq!("hello".to_string(), decoded.body::<String>().unwrap());
+    }
+
+    #[test]
+    fn test_encode_large_body() {
+        let mut message = Message::new();
+        let body: String = (0..10000).map(|_| 'x').collect();
+        message.set_body(&body).unwrap();
+
+        let encoded = message.encode().unwrap();
+        assert!(encoded.len() > 10000);
+        assert_eq!(body, Message::decode(&encoded).unwrap().body::<String>().unwrap());
+    }
+
+    #[test]
+    fn test_maps_survive_encoding() {
+        let mut message = Message::new();
+        message.properties_mut().insert("region", Value::String("eu-west".to_string())).unwrap();
+        message.annotations_mut().insert("x-opt-partition-key", Value::Int(7)).unwrap();
+        message.instructions_mut().insert("x-opt-hops", Value::Ubyte(1)).unwrap();
+
+        let decoded = Message::decode(&message.encode().unwrap()).unwrap();
+        assert_eq!(Some(Value::String("eu-west".to_string())), decoded.properties().get("region"));
+        assert_eq!(Some(Value::Int(7)), decoded.annotations().get("x-opt-partition-key"));
+        assert_eq!(Some(Value::Ubyte(1)), decoded.instructions().get("x-opt-hops"));
+        assert!(decoded.properties().get("missing").is_none());
+    }
+
+    #[test]
+    fn test_decode_garbage() {
+        assert!(Message::decode(&[0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn test_empty_body() {
+        let message = Message::new();
+        assert!(message.body::<String>().is_err());
+    }
+}
@@ -1,10 +1,58 @@
-use libc::size_t;
-use std::str;
+use libc::{c_void, size_t};
+use std::{slice, str};
+use std::ops::{Deref, DerefMut};
 use std::ffi::{CString, CStr};
 
 use proton_sys;
 
+// Handles hold a reference on the proton object they wrap, taken with
+// `pn_incref` in `from_ptr`, so the object stays around for as long as any
+// handle does, even once proton itself is done with it. Cloning a handle
+// takes another reference and dropping it gives one back.
+macro_rules! refcounted {
+    ($t:ident) => (
+        impl Clone for $t {
+            fn clone(&self) -> $t {
+                $t::from_ptr(self.as_ptr())
+            }
+        }
+
+        impl Drop for $t {
+            fn drop(&mut self) {
+                unsafe {proton_sys::pn_decref(self.as_ptr() as *mut ::libc::c_void)};
+            }
+        }
+    )
+}
+
+unsafe fn incref<T>(ptr: *mut T) -> *mut T {
+    proton_sys::pn_incref(ptr as *mut c_void) as *mut T
+}
+
 mod encoder;
+mod decoder;
+mod value;
+#[macro_use] mod described;
+mod message;
+mod data;
+mod error;
+mod terminus;
+mod delivery;
+mod tracker;
+
+pub mod types;
+pub mod codec;
+
+pub use self::value::Value;
+pub use self::message::Message;
+pub use self::data::{DataMap, DataMapMut, MapKey, AnnotationKey};
+pub use self::error::{Error, ErrorKind, Result};
+pub use self::described::{Described, Descriptor};
+pub use self::delivery::{DeliveryState, Outcome};
+pub use self::tracker::Tracker;
+pub use self::terminus::{Terminus, TerminusMut, TerminusType, Durability, ExpiryPolicy, DistributionMode};
+
+use self::types::Symbol;
 
 pub enum Trace {
     OFF,
@@ -13,95 +61,320 @@ pub enum Trace {
     RAW
 }
 
-struct Condition<'cond> {
-    name: &'cond str,
-    description: &'cond str
+/// Error condition an endpoint is closed with, e.g.
+/// `amqp:resource-limit-exceeded`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Condition {
+    pub name: String,
+    pub description: Option<String>,
+    /// map of symbols to values with extra details
+    pub info: Option<Value>
+}
+
+impl Condition {
+    pub fn new(name: &str, description: &str) -> Condition {
+        Condition {
+            name: name.to_string(),
+            description: Some(description.to_string()),
+            info: None
+        }
+    }
+
+    fn from_ptr(ptr: *mut proton_sys::pn_condition_t) -> Option<Condition> {
+        unsafe {
+            if ptr.is_null() || proton_sys::pn_condition_is_set(ptr) == 0 {
+                return None;
+            }
+
+            Some(Condition {
+                name: to_string(proton_sys::pn_condition_get_name(ptr)).unwrap_or(String::new()),
+                description: to_string(proton_sys::pn_condition_get_description(ptr)),
+                info: Value::from_data(proton_sys::pn_condition_info(ptr)).unwrap_or(None)
+            })
+        }
+    }
+
+    fn put(&self, ptr: *mut proton_sys::pn_condition_t) -> Result<()> {
+        let name = try!(c_string(&self.name));
+        let description = match self.description {
+            Some(ref description) => Some(try!(c_string(description))),
+            None => None
+        };
+        if let Some(ref info) = self.info {
+            try!(check_value(info));
+        }
+
+        unsafe {
+            proton_sys::pn_condition_clear(ptr);
+            proton_sys::pn_condition_set_name(ptr, name.as_ptr());
+            if let Some(ref description) = description {
+                proton_sys::pn_condition_set_description(ptr, description.as_ptr());
+            }
+            // checked above, so this can't fail
+            let _ = Value::set_data(proton_sys::pn_condition_info(ptr), self.info.as_ref());
+        }
+        Ok(())
+    }
 }
 
-enum State {
+/// Reads the capabilities held by `data`: an array of symbols, or a single
+/// symbol when there's just one.
+fn symbols(data: *mut proton_sys::pn_data_t) -> Vec<Symbol> {
+    let values = match Value::from_data(data) {
+        Ok(Some(Value::Array(values))) | Ok(Some(Value::List(values))) => values,
+        Ok(Some(value)) => vec![value],
+        Ok(None) | Err(_) => Vec::new()
+    };
+
+    values.into_iter().filter_map(|value| match value {
+        Value::Symbol(s) => Some(Symbol(s)),
+        _ => None
+    }).collect()
+}
+
+fn set_symbols(data: *mut proton_sys::pn_data_t, symbols: &[Symbol]) {
+    // an array of symbols is always valid, so this can't fail
+    let _ = if symbols.is_empty() {
+        Value::set_data(data, None)
+    } else {
+        let values = symbols.iter().map(|s| Value::Symbol(s.0.clone())).collect();
+        Value::set_data(data, Some(&Value::Array(values)))
+    };
+}
+
+unsafe fn to_str<'a>(ptr: *const ::libc::c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        None
+    } else {
+        str::from_utf8(CStr::from_ptr(ptr).to_bytes()).ok()
+    }
+}
+
+unsafe fn to_string(ptr: *const ::libc::c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    str::from_utf8(CStr::from_ptr(ptr).to_bytes()).ok().map(|s| s.to_string())
+}
+
+/// Copies `s` for proton, which can't take a string with a nul byte in it.
+fn c_string(s: &str) -> Result<CString> {
+    CString::new(s).map_err(|_| Error::new(ErrorKind::Argument, "string contains a nul byte"))
+}
+
+/// Checks `value` can be put in a `pn_data_t`, see `Value::validate`.
+fn check_value(value: &Value) -> Result<()> {
+    value.validate().map_err(|_| Error::new(ErrorKind::Argument, "array elements differ in type or descriptor"))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum State {
     UNINIT,
-    CLOSED,
-    ACTIVE
+    ACTIVE,
+    CLOSED
 }
 
-/**
- * (LocalState, RemoteState)
- */
-struct EndpointState(State, State);
+/// Local and remote state of an endpoint.
+///
+/// Also used as a mask to look endpoints up by state, e.g. with
+/// `pn_session_next`, in which case a `None` half matches any state.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct EndpointState {
+    pub local: Option<State>,
+    pub remote: Option<State>
+}
+
+/// Mask matching endpoints in any state.
+pub const ANY_STATE: EndpointState = EndpointState {local: None, remote: None};
 
 impl EndpointState {
-    fn from_bits(bits: i32) -> EndpointState {
-        EndpointState::from_flags(&proton_sys::StateFlags::from_bits(bits).unwrap())
+    pub fn new(local: State, remote: State) -> EndpointState {
+        EndpointState {local: Some(local), remote: Some(remote)}
+    }
+
+    /// Decodes `PN_LOCAL_*`/`PN_REMOTE_*` bits. A half without any bit set
+    /// is `None`, and more than one bit in a half is an error.
+    pub fn from_bits(bits: i32) -> Result<EndpointState> {
+        let flags = match proton_sys::StateFlags::from_bits(bits) {
+            Some(flags) => flags,
+            None => return Err(Error::new(ErrorKind::Argument,
+                                          &format!("invalid endpoint state 0x{:x}", bits)))
+        };
+        EndpointState::from_flags(&flags)
     }
 
-    fn from_flags(flags: &proton_sys::StateFlags) -> EndpointState {
+    pub fn from_flags(flags: &proton_sys::StateFlags) -> Result<EndpointState> {
         let local = match flags.local_state() {
-            proton_sys::LOCAL_ACTIVE => State::ACTIVE,
-            proton_sys::LOCAL_CLOSED => State::CLOSED,
-            _ => State::UNINIT, // need to handle error case7
+            proton_sys::LOCAL_UNINIT => Some(State::UNINIT),
+            proton_sys::LOCAL_ACTIVE => Some(State::ACTIVE),
+            proton_sys::LOCAL_CLOSED => Some(State::CLOSED),
+            f if f.is_empty() => None,
+            _ => return Err(Error::new(ErrorKind::Argument,
+                                       &format!("invalid local state 0x{:x}", flags.bits())))
         };
 
-        let remote = match flags.local_state() {
-            proton_sys::REMOTE_ACTIVE => State::ACTIVE,
-            proton_sys::REMOTE_CLOSED => State::CLOSED,
-            _ => State::UNINIT,
+        let remote = match flags.remote_state() {
+            proton_sys::REMOTE_UNINIT => Some(State::UNINIT),
+            proton_sys::REMOTE_ACTIVE => Some(State::ACTIVE),
+            proton_sys::REMOTE_CLOSED => Some(State::CLOSED),
+            f if f.is_empty() => None,
+            _ => return Err(Error::new(ErrorKind::Argument,
+                                       &format!("invalid remote state 0x{:x}", flags.bits())))
         };
 
-        EndpointState(local, remote)
+        Ok(EndpointState {local: local, remote: remote})
     }
 
-    fn as_bits(&self) -> i32 {
+    pub fn as_bits(&self) -> i32 {
         self.as_flags().bits()
     }
 
-    fn as_flags(&self) -> proton_sys::StateFlags {
-        let local = match self.0 {
-            State::ACTIVE => proton_sys::LOCAL_ACTIVE,
-            State::CLOSED  => proton_sys::LOCAL_CLOSED,
-            State::UNINIT => proton_sys::LOCAL_UNINIT
+    pub fn as_flags(&self) -> proton_sys::StateFlags {
+        let local = match self.local {
+            Some(State::UNINIT) => proton_sys::LOCAL_UNINIT,
+            Some(State::ACTIVE) => proton_sys::LOCAL_ACTIVE,
+            Some(State::CLOSED) => proton_sys::LOCAL_CLOSED,
+            None => proton_sys::StateFlags::empty()
         };
 
-        let remote = match self.1 {
-            State::ACTIVE => proton_sys::REMOTE_ACTIVE,
-            State::CLOSED  => proton_sys::REMOTE_CLOSED,
-            State::UNINIT => proton_sys::REMOTE_UNINIT
+        let remote = match self.remote {
+            Some(State::UNINIT) => proton_sys::REMOTE_UNINIT,
+            Some(State::ACTIVE) => proton_sys::REMOTE_ACTIVE,
+            Some(State::CLOSED) => proton_sys::REMOTE_CLOSED,
+            None => proton_sys::StateFlags::empty()
         };
 
-        local & remote
+        local | remote
     }
-}
 
-trait Endpoint {
-    fn condition(&self) -> (Condition, Condition);
-}
+    /// Whether `state` matches this one used as a mask.
+    pub fn matches(&self, state: &EndpointState) -> bool {
+        (self.local.is_none() || self.local == state.local) &&
+            (self.remote.is_none() || self.remote == state.remote)
+    }
 
-// implement endpoint
-struct Session(*mut proton_sys::pn_session_t);
+    /// Open at both ends.
+    pub fn is_active(&self) -> bool {
+        self.local == Some(State::ACTIVE) && self.remote == Some(State::ACTIVE)
+    }
 
-impl Session {
+    /// Closed at both ends.
+    pub fn is_closed(&self) -> bool {
+        self.local == Some(State::CLOSED) && self.remote == Some(State::CLOSED)
+    }
 
-    fn from_ptr(ptr: *mut proton_sys::pn_session_t) -> Session {
-        Session(ptr)
+    /// The peer opened its end and we haven't opened ours yet.
+    pub fn is_remote_opening(&self) -> bool {
+        self.local == Some(State::UNINIT) && self.remote == Some(State::ACTIVE)
     }
 
-    fn open(&mut self) {
-        unsafe { proton_sys::pn_session_open(&mut *self.0); }
+    /// The peer closed its end while ours is still open.
+    pub fn is_remote_closing(&self) -> bool {
+        self.local == Some(State::ACTIVE) && self.remote == Some(State::CLOSED)
     }
 
-    fn close(&mut self) {
-        // update condition
-        unsafe { proton_sys::pn_session_close(&mut *self.0); }
+    pub fn is_local_closed(&self) -> bool {
+        self.local == Some(State::CLOSED)
     }
 
-    fn state(&mut self) -> EndpointState {
-        unsafe {
-            let state = proton_sys::pn_session_state(&mut *self.0);
-            EndpointState::from_bits(state)
+    pub fn is_remote_closed(&self) -> bool {
+        self.remote == Some(State::CLOSED)
+    }
+}
+
+/// What connections, sessions and links have in common: they're opened
+/// and closed at each end independently, and closing one can carry an
+/// error condition.
+pub trait Endpoint {
+    fn state(&self) -> EndpointState;
+
+    fn open(&mut self);
+
+    fn close(&mut self);
+
+    /// Local condition, sent to the peer on close.
+    fn condition(&self) -> Option<Condition>;
+
+    /// Fails with `ErrorKind::Argument` if the name or the description
+    /// has a nul byte in it.
+    fn set_condition(&mut self, condition: &Condition) -> Result<()>;
+
+    /// Condition the peer closed its end with.
+    fn remote_condition(&self) -> Option<Condition>;
+
+    /// Closes with an error condition.
+    fn close_with(&mut self, condition: &Condition) -> Result<()> {
+        try!(self.set_condition(condition));
+        self.close();
+        Ok(())
+    }
+}
+
+macro_rules! endpoint {
+    ($t:ty, $state:ident, $open:ident, $close:ident, $condition:ident, $remote_condition:ident) => (
+        impl Endpoint for $t {
+            fn state(&self) -> EndpointState {
+                let bits = unsafe {proton_sys::$state(self.as_ptr())};
+                // proton always sets exactly one local and one remote bit
+                EndpointState::from_bits(bits).unwrap()
+            }
+
+            fn open(&mut self) {
+                unsafe {proton_sys::$open(self.as_ptr())};
+            }
+
+            fn close(&mut self) {
+                unsafe {proton_sys::$close(self.as_ptr())};
+            }
+
+            fn condition(&self) -> Option<Condition> {
+                Condition::from_ptr(unsafe {proton_sys::$condition(self.as_ptr())})
+            }
+
+            fn set_condition(&mut self, condition: &Condition) -> Result<()> {
+                condition.put(unsafe {proton_sys::$condition(self.as_ptr())})
+            }
+
+            fn remote_condition(&self) -> Option<Condition> {
+                Condition::from_ptr(unsafe {proton_sys::$remote_condition(self.as_ptr())})
+            }
         }
+    )
+}
+
+refcounted!(Connection);
+refcounted!(Session);
+refcounted!(Sender);
+refcounted!(Receiver);
+refcounted!(Delivery);
+
+endpoint!(Connection, pn_connection_state, pn_connection_open, pn_connection_close,
+          pn_connection_condition, pn_connection_remote_condition);
+endpoint!(Session, pn_session_state, pn_session_open, pn_session_close,
+          pn_session_condition, pn_session_remote_condition);
+endpoint!(Link, pn_link_state, pn_link_open, pn_link_close,
+          pn_link_condition, pn_link_remote_condition);
+
+pub struct Session(*mut proton_sys::pn_session_t);
+
+impl Session {
+
+    fn from_ptr(ptr: *mut proton_sys::pn_session_t) -> Session {
+        Session(unsafe {incref(ptr)})
     }
 
-    fn next(&mut self, state: &EndpointState) -> Session {
-        Session(unsafe{proton_sys::pn_session_next(&mut *self.0, state.as_bits())})
+    fn as_ptr(&self) -> *mut proton_sys::pn_session_t {
+        self.0
+    }
+
+    /// The next session of the connection in a state matching `mask`.
+    pub fn next(&self, mask: &EndpointState) -> Option<Session> {
+        let next = unsafe {proton_sys::pn_session_next(self.0, mask.as_bits())};
+        if next.is_null() {
+            None
+        } else {
+            Some(Session::from_ptr(next))
+        }
     }
 
     fn connection(&mut self) -> Connection {
@@ -124,39 +397,98 @@ impl Session {
         unsafe { proton_sys::pn_session_outgoing_bytes(&mut *self.0) }
     }
 
-    fn sender(&mut self, name: &str) -> Link {
-        let unique = unsafe{
-            let n = CString::new(name).unwrap();
-            proton_sys::pn_sender(&mut *self.0, n.as_ptr())
-        };
-        Link::Sender(Sender(unique))
+    pub fn sender(&mut self, name: &str) -> Result<Link> {
+        let name = try!(c_string(name));
+        let unique = unsafe {proton_sys::pn_sender(&mut *self.0, name.as_ptr())};
+        Ok(Link::Sender(Sender::from_ptr(unique)))
     }
 
-    fn receiver(&mut self, name: &str) -> Link {
-        let unique = unsafe{
-            let n = CString::new(name).unwrap();
-            proton_sys::pn_receiver(&mut *self.0, n.as_ptr())
-        };
-        Link::Receiver(Receiver(unique))
+    pub fn receiver(&mut self, name: &str) -> Result<Link> {
+        let name = try!(c_string(name));
+        let unique = unsafe {proton_sys::pn_receiver(&mut *self.0, name.as_ptr())};
+        Ok(Link::Receiver(Receiver::from_ptr(unique)))
     }
 
-    // move to dtor
-    fn free(&mut self) {
-        unsafe { proton_sys::pn_session_free(&mut *self.0); }
+    /// Tells proton the application is done with the session. Other handles
+    /// to it stay valid.
+    pub fn free(self) {
+        unsafe {proton_sys::pn_session_free(self.0)};
     }
 }
 
-struct Sender(*mut proton_sys::pn_link_t);
-struct Receiver(*mut proton_sys::pn_link_t);
+pub struct Sender(*mut proton_sys::pn_link_t);
+pub struct Receiver(*mut proton_sys::pn_link_t);
+
+/// When the sender settles its deliveries.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SndSettleMode {
+    /// after the receiver has settled, for at-least-once
+    Unsettled,
+    /// right away, for at-most-once
+    Settled,
+    /// either, delivery by delivery
+    Mixed,
+}
+
+impl SndSettleMode {
+    fn from_raw(mode: proton_sys::pn_snd_settle_mode_t) -> SndSettleMode {
+        match mode {
+            proton_sys::PN_SND_UNSETTLED => SndSettleMode::Unsettled,
+            proton_sys::PN_SND_SETTLED => SndSettleMode::Settled,
+            _ => SndSettleMode::Mixed
+        }
+    }
+
+    fn as_raw(&self) -> proton_sys::pn_snd_settle_mode_t {
+        match *self {
+            SndSettleMode::Unsettled => proton_sys::PN_SND_UNSETTLED,
+            SndSettleMode::Settled => proton_sys::PN_SND_SETTLED,
+            SndSettleMode::Mixed => proton_sys::PN_SND_MIXED,
+        }
+    }
+}
+
+/// When the receiver settles its deliveries.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RcvSettleMode {
+    /// right away
+    First,
+    /// only once the sender has settled, for exactly-once
+    Second,
+}
+
+impl RcvSettleMode {
+    fn from_raw(mode: proton_sys::pn_rcv_settle_mode_t) -> RcvSettleMode {
+        match mode {
+            proton_sys::PN_RCV_FIRST => RcvSettleMode::First,
+            _ => RcvSettleMode::Second
+        }
+    }
+
+    fn as_raw(&self) -> proton_sys::pn_rcv_settle_mode_t {
+        match *self {
+            RcvSettleMode::First => proton_sys::PN_RCV_FIRST,
+            RcvSettleMode::Second => proton_sys::PN_RCV_SECOND,
+        }
+    }
+}
 
-// Implement endpoint
-enum Link {
+#[derive(Clone)]
+pub enum Link {
     Sender(Sender),
     Receiver(Receiver),
 }
 
 impl Link {
 
+    fn from_ptr(ptr: *mut proton_sys::pn_link_t) -> Link {
+        if unsafe {proton_sys::pn_link_is_sender(ptr) != 0} {
+            Link::Sender(Sender::from_ptr(ptr))
+        } else {
+            Link::Receiver(Receiver::from_ptr(ptr))
+        }
+    }
+
     fn get_mut(&mut self) -> &mut proton_sys::pn_link_t {
         match *self {
             Link::Sender(Sender(ref mut p)) |
@@ -164,24 +496,47 @@ impl Link {
         }
     }
 
-    fn open(&mut self) {
-        unsafe {proton_sys::pn_link_open(self.get_mut());}
+    fn as_ptr(&self) -> *mut proton_sys::pn_link_t {
+        match *self {
+            Link::Sender(Sender(p)) | Link::Receiver(Receiver(p)) => p
+        }
+    }
+
+    pub fn source(&self) -> Terminus {
+        unsafe {Terminus::new(proton_sys::pn_link_source(self.as_ptr()))}
     }
 
-    fn close(&mut self) {
-        // update condition missing
-        unsafe {proton_sys::pn_link_close(self.get_mut());}
+    pub fn source_mut(&mut self) -> TerminusMut {
+        unsafe {TerminusMut::new(proton_sys::pn_link_source(self.as_ptr()))}
     }
 
-    fn state(&mut self) -> EndpointState {
-        unsafe {
-            let state = proton_sys::pn_link_state(self.get_mut());
-            EndpointState::from_bits(state)
-        }
+    pub fn target(&self) -> Terminus {
+        unsafe {Terminus::new(proton_sys::pn_link_target(self.as_ptr()))}
+    }
+
+    pub fn target_mut(&mut self) -> TerminusMut {
+        unsafe {TerminusMut::new(proton_sys::pn_link_target(self.as_ptr()))}
+    }
+
+    /// Source the peer attached with.
+    pub fn remote_source(&self) -> Terminus {
+        unsafe {Terminus::new(proton_sys::pn_link_remote_source(self.as_ptr()))}
     }
 
-    // Missing source, target, remote_source, remote_target, session
-    // delivery, current
+    /// Target the peer attached with.
+    pub fn remote_target(&self) -> Terminus {
+        unsafe {Terminus::new(proton_sys::pn_link_remote_target(self.as_ptr()))}
+    }
+
+    /// The delivery being sent or received, if any.
+    pub fn current(&self) -> Option<Delivery> {
+        let current = unsafe {proton_sys::pn_link_current(self.as_ptr())};
+        if current.is_null() {
+            None
+        } else {
+            Some(Delivery::from_ptr(current))
+        }
+    }
 
     fn session(&mut self) -> Session {
         match *self {
@@ -197,29 +552,56 @@ impl Link {
         self.session().connection()
     }
 
-    fn advance(&mut self) -> bool{
-        unsafe {proton_sys::pn_link_advance(self.get_mut()) == 0}
+    /// Moves on from the current delivery, once it's been fully sent or
+    /// read. Returns whether there was one to move on from.
+    pub fn advance(&mut self) -> bool {
+        unsafe {proton_sys::pn_link_advance(self.get_mut()) != 0}
     }
 
-    fn unsettled(&mut self) -> i32 {
+    /// Number of deliveries not settled yet.
+    pub fn unsettled(&mut self) -> i32 {
         unsafe {proton_sys::pn_link_unsettled(self.get_mut())}
     }
 
-    fn credit(&mut self) -> i32 {
+    /// Iterates over the deliveries not settled yet, oldest first.
+    pub fn unsettled_deliveries(&self) -> Unsettled {
+        let head = unsafe {proton_sys::pn_unsettled_head(self.as_ptr())};
+        Unsettled {
+            next: if head.is_null() {None} else {Some(Delivery::from_ptr(head))}
+        }
+    }
+
+    /// Messages the sender may still send.
+    pub fn credit(&mut self) -> i32 {
         unsafe {proton_sys::pn_link_credit(self.get_mut())}
     }
 
-    fn available(&mut self) {
-        unsafe {proton_sys::pn_link_available(self.get_mut());}
+    /// Credit as last seen by the peer.
+    pub fn remote_credit(&mut self) -> i32 {
+        unsafe {proton_sys::pn_link_remote_credit(self.get_mut())}
+    }
+
+    /// Messages the sender says it has, see `Sender::offered`.
+    pub fn available(&mut self) -> i32 {
+        unsafe {proton_sys::pn_link_available(self.get_mut())}
     }
 
-    fn queued(&mut self) -> i32 {
+    /// Deliveries buffered on the link.
+    pub fn queued(&mut self) -> i32 {
         unsafe {proton_sys::pn_link_queued(self.get_mut())}
     }
 
-    // missing next
+    /// The next link of the connection in a state matching `mask`.
+    pub fn next(&self, mask: &EndpointState) -> Option<Link> {
+        let next = unsafe {proton_sys::pn_link_next(self.as_ptr(), mask.as_bits())};
+        if next.is_null() {
+            None
+        } else {
+            Some(Link::from_ptr(next))
+        }
+    }
 
-    fn name(&mut self) -> &str {
+    pub fn name(&mut self) -> &str {
         unsafe {
             let name = CStr::from_ptr(proton_sys::pn_link_name(self.get_mut()));
             str::from_utf8(name.to_bytes()).unwrap()
@@ -228,17 +610,41 @@ impl Link {
 
     // is_sender, is_receiver (use enum?)
 
-    fn remote_snd_settle_mode(&mut self) {
-        unsafe {proton_sys::pn_link_remote_snd_settle_mode(self.get_mut());}
+    pub fn snd_settle_mode(&mut self) -> SndSettleMode {
+        SndSettleMode::from_raw(unsafe {proton_sys::pn_link_snd_settle_mode(self.get_mut())})
     }
 
-    fn remote_rcv_settle_mode(&mut self) {
-        unsafe {proton_sys::pn_link_remote_rcv_settle_mode(self.get_mut());}
+    /// Only takes effect when set before the link is opened.
+    pub fn set_snd_settle_mode(&mut self, mode: SndSettleMode) {
+        unsafe {proton_sys::pn_link_set_snd_settle_mode(self.get_mut(), mode.as_raw())};
     }
 
-    // get_drain, set_drain
+    pub fn rcv_settle_mode(&mut self) -> RcvSettleMode {
+        RcvSettleMode::from_raw(unsafe {proton_sys::pn_link_rcv_settle_mode(self.get_mut())})
+    }
+
+    /// Only takes effect when set before the link is opened.
+    pub fn set_rcv_settle_mode(&mut self, mode: RcvSettleMode) {
+        unsafe {proton_sys::pn_link_set_rcv_settle_mode(self.get_mut(), mode.as_raw())};
+    }
+
+    pub fn remote_snd_settle_mode(&mut self) -> SndSettleMode {
+        SndSettleMode::from_raw(unsafe {proton_sys::pn_link_remote_snd_settle_mode(self.get_mut())})
+    }
+
+    pub fn remote_rcv_settle_mode(&mut self) -> RcvSettleMode {
+        RcvSettleMode::from_raw(unsafe {proton_sys::pn_link_remote_rcv_settle_mode(self.get_mut())})
+    }
 
-    fn drained(&mut self) -> i32 {
+    /// Whether the receiver asked for the credit it gave to be used up or
+    /// given back.
+    pub fn drain_mode(&mut self) -> bool {
+        unsafe {proton_sys::pn_link_get_drain(self.get_mut()) != 0}
+    }
+
+    /// On a sender in drain mode, gives back the credit left over once
+    /// there's nothing more to send, and returns how much that was.
+    pub fn drained(&mut self) -> i32 {
         unsafe {proton_sys::pn_link_drained(self.get_mut())}
     }
 
@@ -246,87 +652,348 @@ impl Link {
         unsafe {proton_sys::pn_link_detach(self.get_mut());}
     }
 
-    fn free(&mut self) {
-        unsafe {proton_sys::pn_link_free(self.get_mut());}
+    /// Tells proton the application is done with the link. Other handles
+    /// to it stay valid.
+    pub fn free(self) {
+        unsafe {proton_sys::pn_link_free(self.as_ptr())};
+    }
+}
+
+/// A message transfer on a link, identified by its tag.
+pub struct Delivery(*mut proton_sys::pn_delivery_t);
+
+impl Delivery {
+    fn from_ptr(ptr: *mut proton_sys::pn_delivery_t) -> Delivery {
+        Delivery(unsafe {incref(ptr)})
+    }
+
+    fn as_ptr(&self) -> *mut proton_sys::pn_delivery_t {
+        self.0
     }
 }
 
 impl Sender {
-    fn offered(&mut self, credits: i32) {
-        unsafe {proton_sys::pn_link_offered(&mut *self.0, credits);}
+    fn from_ptr(ptr: *mut proton_sys::pn_link_t) -> Sender {
+        Sender(unsafe {incref(ptr)})
     }
 
-    fn send(&mut self, bytes: &[u8]) {
-        let s = CString::new(bytes).unwrap();
-        unsafe{
-            let sent = proton_sys::pn_link_send(&mut *self.0,
-                                                s.as_ptr(),
-                                                bytes.len() as proton_sys::size_t);
-            assert_eq!(bytes.len(), sent as usize);
-        }
+    fn as_ptr(&self) -> *mut proton_sys::pn_link_t {
+        self.0
+    }
+}
+
+impl Receiver {
+    fn from_ptr(ptr: *mut proton_sys::pn_link_t) -> Receiver {
+        Receiver(unsafe {incref(ptr)})
+    }
+
+    fn as_ptr(&self) -> *mut proton_sys::pn_link_t {
+        self.0
     }
 }
 
 impl Sender {
-    fn flow(&mut self, credits: i32) {
-        unsafe {proton_sys::pn_link_flow(&mut *self.0, credits);}
+    /// Tells the receiver how many messages we have for it.
+    pub fn offered(&mut self, credits: i32) {
+        unsafe {proton_sys::pn_link_offered(self.0, credits)};
     }
 
-    fn recv(&mut self, limit: u32) -> Vec<i8> {
-        let mut dst = Vec::with_capacity(limit as usize);
-        unsafe{
-            let sent = proton_sys::pn_link_send(&mut *self.0,
-                                                dst.as_mut_ptr(),
-                                                limit as proton_sys::size_t);
-            assert_eq!(limit as usize, sent as usize);
+    /// Adds `bytes` to the current delivery and returns how many were
+    /// taken, which may be fewer than asked when proton's buffer is full.
+    pub fn send(&mut self, bytes: &[u8]) -> Result<usize> {
+        let sent = unsafe {
+            proton_sys::pn_link_send(self.0,
+                                     bytes.as_ptr() as *const ::libc::c_char,
+                                     bytes.len() as proton_sys::size_t)
+        };
+
+        if sent < 0 {
+            Err(Error::from_code(sent as i32))
+        } else {
+            Ok(sent as usize)
         }
-        dst
     }
 }
 
-pub struct Message {
-    ptr: *mut proton_sys::pn_message_t
-}
+impl Receiver {
+    /// Gives the sender credit for `credits` more messages.
+    pub fn flow(&mut self, credits: i32) {
+        unsafe {proton_sys::pn_link_flow(self.0, credits)};
+    }
 
-impl Message {
-    pub fn new() -> Message {
-        let message = unsafe {proton_sys::pn_message()};
-        Message {
-            ptr: message
+    /// Gives `credits` more credit and asks the sender to use all of it
+    /// up, or give back what it can't use.
+    pub fn drain(&mut self, credits: i32) {
+        unsafe {proton_sys::pn_link_drain(self.0, credits)};
+    }
+
+    pub fn set_drain(&mut self, drain: bool) {
+        unsafe {proton_sys::pn_link_set_drain(self.0, drain as u8)};
+    }
+
+    /// Whether a drain is still waiting on the sender.
+    pub fn draining(&mut self) -> bool {
+        unsafe {proton_sys::pn_link_draining(self.0) != 0}
+    }
+
+    /// Reads data of the current delivery into `buf`. Returns how many
+    /// bytes were read, or `None` once the whole delivery has been read.
+    pub fn recv(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+        let read = unsafe {
+            proton_sys::pn_link_recv(self.0,
+                                     buf.as_mut_ptr() as *mut ::libc::c_char,
+                                     buf.len() as proton_sys::size_t)
+        };
+
+        if read == proton_sys::PN_EOS as proton_sys::ssize_t {
+            Ok(None)
+        } else if read < 0 {
+            Err(Error::from_code(read as i32))
+        } else {
+            Ok(Some(read as usize))
         }
     }
+
+    /// Reads and decodes the message of the current delivery once it has
+    /// fully arrived, and moves on to the next delivery. The delivery is
+    /// returned too, together with the message or the reason it couldn't
+    /// be decoded, so it can be accepted or rejected.
+    pub fn recv_message(&mut self) -> Result<Option<(Delivery, Result<Message>)>> {
+        let delivery = match unsafe {proton_sys::pn_link_current(self.0)} {
+            ptr if ptr.is_null() => return Ok(None),
+            ptr => Delivery::from_ptr(ptr)
+        };
+
+        if !delivery.readable() || delivery.partial() {
+            return Ok(None);
+        }
+
+        let mut bytes = vec![0; delivery.pending()];
+        let mut len = 0;
+        loop {
+            if len == bytes.len() {
+                bytes.extend(::std::iter::repeat(0).take(1024));
+            }
+            match try!(self.recv(&mut bytes[len..])) {
+                Some(n) => len += n,
+                None => break
+            }
+        }
+
+        let message = Message::decode(&bytes[..len]);
+        unsafe {proton_sys::pn_link_advance(self.0)};
+        Ok(Some((delivery, message)))
+    }
 }
 
-// implement endpoint
 pub struct Connection {
     ptr: *mut proton_sys::pn_connection_t
 }
 
 impl Connection {
     pub fn new() -> Connection {
-        Connection::from_ptr(unsafe {proton_sys::pn_connection()})
+        unsafe {
+            let ptr = proton_sys::pn_connection();
+            let connection = Connection::from_ptr(ptr);
+            // hand the reference we got from pn_connection over to the handle
+            proton_sys::pn_decref(ptr as *mut c_void);
+            connection
+        }
     }
 
     fn from_ptr(ptr: *mut proton_sys::pn_connection_t) -> Connection {
         Connection {
-            ptr: ptr
+            ptr: unsafe {incref(ptr)}
+        }
+    }
+
+    fn as_ptr(&self) -> *mut proton_sys::pn_connection_t {
+        self.ptr
+    }
+
+    pub fn session(&mut self) -> Session {
+        Session::from_ptr(unsafe {proton_sys::pn_session(self.ptr)})
+    }
+
+    /// Brings the connection back to its initial state so it can be opened
+    /// again.
+    pub fn reset(&mut self) {
+        unsafe {proton_sys::pn_connection_reset(self.ptr)};
+    }
+
+    /// Our container id, sent to the peer on open.
+    pub fn container(&self) -> Option<&str> {
+        unsafe {to_str(proton_sys::pn_connection_get_container(self.ptr))}
+    }
+
+    pub fn set_container(&mut self, container: &str) -> Result<()> {
+        let container = try!(c_string(container));
+        unsafe {proton_sys::pn_connection_set_container(self.ptr, container.as_ptr())};
+        Ok(())
+    }
+
+    /// Hostname sent to the peer on open, for virtual hosting.
+    pub fn hostname(&self) -> Option<&str> {
+        unsafe {to_str(proton_sys::pn_connection_get_hostname(self.ptr))}
+    }
+
+    pub fn set_hostname(&mut self, hostname: &str) -> Result<()> {
+        let hostname = try!(c_string(hostname));
+        unsafe {proton_sys::pn_connection_set_hostname(self.ptr, hostname.as_ptr())};
+        Ok(())
+    }
+
+    /// User to authenticate as, when the transport uses SASL.
+    pub fn user(&self) -> Option<&str> {
+        unsafe {to_str(proton_sys::pn_connection_get_user(self.ptr))}
+    }
+
+    pub fn set_user(&mut self, user: &str) -> Result<()> {
+        let user = try!(c_string(user));
+        unsafe {proton_sys::pn_connection_set_user(self.ptr, user.as_ptr())};
+        Ok(())
+    }
+
+    /// Proton has no getter for the password.
+    pub fn set_password(&mut self, password: &str) -> Result<()> {
+        let password = try!(c_string(password));
+        unsafe {proton_sys::pn_connection_set_password(self.ptr, password.as_ptr())};
+        Ok(())
+    }
+
+    pub fn offered_capabilities(&self) -> Vec<Symbol> {
+        symbols(unsafe {proton_sys::pn_connection_offered_capabilities(self.ptr)})
+    }
+
+    pub fn set_offered_capabilities(&mut self, capabilities: &[Symbol]) {
+        set_symbols(unsafe {proton_sys::pn_connection_offered_capabilities(self.ptr)},
+                    capabilities);
+    }
+
+    pub fn desired_capabilities(&self) -> Vec<Symbol> {
+        symbols(unsafe {proton_sys::pn_connection_desired_capabilities(self.ptr)})
+    }
+
+    pub fn set_desired_capabilities(&mut self, capabilities: &[Symbol]) {
+        set_symbols(unsafe {proton_sys::pn_connection_desired_capabilities(self.ptr)},
+                    capabilities);
+    }
+
+    /// Properties sent to the peer on open.
+    pub fn properties(&self) -> DataMap<Symbol> {
+        unsafe {DataMap::new(proton_sys::pn_connection_properties(self.ptr))}
+    }
+
+    pub fn properties_mut(&mut self) -> DataMapMut<Symbol> {
+        unsafe {DataMapMut::new(proton_sys::pn_connection_properties(self.ptr))}
+    }
+
+    /// The peer's container id, once it has opened its end.
+    pub fn remote_container(&self) -> Option<&str> {
+        unsafe {to_str(proton_sys::pn_connection_remote_container(self.ptr))}
+    }
+
+    pub fn remote_hostname(&self) -> Option<&str> {
+        unsafe {to_str(proton_sys::pn_connection_remote_hostname(self.ptr))}
+    }
+
+    pub fn remote_offered_capabilities(&self) -> Vec<Symbol> {
+        symbols(unsafe {proton_sys::pn_connection_remote_offered_capabilities(self.ptr)})
+    }
+
+    pub fn remote_desired_capabilities(&self) -> Vec<Symbol> {
+        symbols(unsafe {proton_sys::pn_connection_remote_desired_capabilities(self.ptr)})
+    }
+
+    pub fn remote_properties(&self) -> DataMap<Symbol> {
+        unsafe {DataMap::new(proton_sys::pn_connection_remote_properties(self.ptr))}
+    }
+
+    /// Iterates over the sessions in a state matching `mask`, e.g. those the
+    /// peer opened that we haven't opened yet.
+    pub fn sessions(&self, mask: &EndpointState) -> Sessions {
+        let head = unsafe {proton_sys::pn_session_head(self.ptr, mask.as_bits())};
+        Sessions {
+            next: if head.is_null() {None} else {Some(Session::from_ptr(head))},
+            mask: *mask
+        }
+    }
+
+    /// Iterates over the links of all sessions in a state matching `mask`.
+    pub fn links(&self, mask: &EndpointState) -> Links {
+        let head = unsafe {proton_sys::pn_link_head(self.ptr, mask.as_bits())};
+        Links {
+            next: if head.is_null() {None} else {Some(Link::from_ptr(head))},
+            mask: *mask
         }
     }
 
-    pub fn transport(&mut self) -> Transport {
-        Transport::from_ptr(unsafe {proton_sys::pn_connection_transport(self.ptr)})
+    /// Tells proton the application is done with the connection, freeing
+    /// the sessions and links it still has. Handles to any of them stay
+    /// valid until they're dropped.
+    pub fn release(self) {
+        unsafe {proton_sys::pn_connection_release(self.ptr)};
     }
 
-    // collect, (get|set)_container
+    // collect
 
 }
 
-//impl Endpoint for Connection {
-//    fn condition(&self) -> (Condition, Condition) {
-//        unsafe{(proton_sys::pn_connection_condition(self.connection.0),
-//                proton_sys::pn_connection_remote_condition(self.connection.0))}
-//    }
-//}
+/// Sessions of a connection, see `Connection::sessions`.
+pub struct Sessions {
+    next: Option<Session>,
+    mask: EndpointState
+}
+
+impl Iterator for Sessions {
+    type Item = Session;
+
+    fn next(&mut self) -> Option<Session> {
+        let current = self.next.take();
+        if let Some(ref session) = current {
+            self.next = session.next(&self.mask);
+        }
+        current
+    }
+}
+
+/// Links of a connection, see `Connection::links`.
+pub struct Links {
+    next: Option<Link>,
+    mask: EndpointState
+}
+
+impl Iterator for Links {
+    type Item = Link;
+
+    fn next(&mut self) -> Option<Link> {
+        let current = self.next.take();
+        if let Some(ref link) = current {
+            self.next = link.next(&self.mask);
+        }
+        current
+    }
+}
+
+/// Unsettled deliveries of a link, see `Link::unsettled_deliveries`.
+pub struct Unsettled {
+    next: Option<Delivery>
+}
+
+impl Iterator for Unsettled {
+    type Item = Delivery;
+
+    fn next(&mut self) -> Option<Delivery> {
+        let current = self.next.take();
+        if let Some(ref delivery) = current {
+            let next = unsafe {proton_sys::pn_unsettled_next(delivery.as_ptr())};
+            if !next.is_null() {
+                self.next = Some(Delivery::from_ptr(next));
+            }
+        }
+        current
+    }
+}
 
 pub struct Container;
 
@@ -336,27 +1003,141 @@ impl Container {
     }
 }
 
+/// Moves frames between a connection and its peer.
+///
+/// Unlike the other handles, a transport has a single owner: it can't be
+/// cloned, and a connection doesn't hand out the one it's bound to, so
+/// nothing can touch the buffers behind a `Tail` or `Head`.
+///
+/// ```compile_fail
+/// let transport = rust_proton::Transport::client();
+/// let other: rust_proton::Transport = transport.clone();
+/// ```
+///
+/// ```compile_fail
+/// let mut transport = rust_proton::Transport::client();
+/// let tail = transport.tail().unwrap();
+/// transport.push(b"AMQP").unwrap();
+/// tail.process(0).unwrap();
+/// ```
 pub struct Transport {
     ptr: *mut proton_sys::pn_transport_t
 }
 
+impl Drop for Transport {
+    fn drop(&mut self) {
+        unsafe {proton_sys::pn_decref(self.ptr as *mut c_void)};
+    }
+}
+
 impl Transport {
-    pub fn new() -> Transport {
-        let transport;
+    /// Transport for a connection we initiated.
+    pub fn client() -> Transport {
         unsafe {
-            transport = proton_sys::pn_transport();
-            proton_sys::pn_transport_set_server(transport);
-        };
+            let ptr = proton_sys::pn_transport();
+            let transport = Transport::from_ptr(ptr);
+            proton_sys::pn_decref(ptr as *mut c_void);
+            transport
+        }
+    }
 
-        Transport::from_ptr(transport)
+    /// Transport for a connection a peer initiated, e.g. one a listener
+    /// accepted.
+    pub fn server() -> Transport {
+        let transport = Transport::client();
+        unsafe {proton_sys::pn_transport_set_server(transport.ptr)};
+        transport
     }
 
-    pub fn from_ptr(ptr: *mut proton_sys::pn_transport_t) -> Transport {
+    /// Wraps a transport owned by someone else, taking a reference on it.
+    fn from_ptr(ptr: *mut proton_sys::pn_transport_t) -> Transport {
         Transport {
-            ptr: ptr
+            ptr: unsafe {incref(ptr)}
         }
     }
 
+    fn as_ptr(&self) -> *mut proton_sys::pn_transport_t {
+        self.ptr
+    }
+
+    /// Largest frame we accept, in bytes.
+    pub fn max_frame(&self) -> u32 {
+        unsafe {proton_sys::pn_transport_get_max_frame(self.ptr)}
+    }
+
+    /// Only takes effect when set before the connection is opened.
+    pub fn set_max_frame(&mut self, size: u32) {
+        unsafe {proton_sys::pn_transport_set_max_frame(self.ptr, size)};
+    }
+
+    /// Largest frame the peer accepts, once it has opened its end.
+    pub fn remote_max_frame(&self) -> u32 {
+        unsafe {proton_sys::pn_transport_get_remote_max_frame(self.ptr)}
+    }
+
+    /// Highest channel number, i.e. how many sessions there can be at once
+    /// minus one.
+    pub fn channel_max(&self) -> u16 {
+        unsafe {proton_sys::pn_transport_get_channel_max(self.ptr)}
+    }
+
+    /// Fails once the connection has been opened.
+    pub fn set_channel_max(&mut self, channel_max: u16) -> Result<()> {
+        match unsafe {proton_sys::pn_transport_set_channel_max(self.ptr, channel_max)} {
+            0 => Ok(()),
+            code => Err(Error::from_code(code))
+        }
+    }
+
+    pub fn remote_channel_max(&self) -> u16 {
+        unsafe {proton_sys::pn_transport_remote_channel_max(self.ptr)}
+    }
+
+    /// Milliseconds without traffic after which we consider the peer gone,
+    /// or 0 for none.
+    pub fn idle_timeout(&self) -> u32 {
+        unsafe {proton_sys::pn_transport_get_idle_timeout(self.ptr)}
+    }
+
+    pub fn set_idle_timeout(&mut self, timeout: u32) {
+        unsafe {proton_sys::pn_transport_set_idle_timeout(self.ptr, timeout)};
+    }
+
+    /// The peer's idle timeout, which we have to send something within.
+    pub fn remote_idle_timeout(&self) -> u32 {
+        unsafe {proton_sys::pn_transport_get_remote_idle_timeout(self.ptr)}
+    }
+
+    /// Lets the transport act on the time that passed: it closes the
+    /// connection when the peer has been silent for longer than our idle
+    /// timeout, and queues an empty frame when we have to show the peer
+    /// we're still around. `now` is in milliseconds, from any clock that
+    /// doesn't go backwards.
+    ///
+    /// Returns when it has to be called again, if ever.
+    pub fn tick(&mut self, now: i64) -> Option<i64> {
+        match unsafe {proton_sys::pn_transport_tick(self.ptr, now)} {
+            0 => None,
+            deadline => Some(deadline)
+        }
+    }
+
+    /// Whether both ends of the transport are closed, so there's nothing
+    /// more to read or write.
+    pub fn closed(&mut self) -> bool {
+        unsafe {proton_sys::pn_transport_closed(self.ptr) != 0}
+    }
+
+    /// Frames written so far.
+    pub fn frames_output(&self) -> u64 {
+        unsafe {proton_sys::pn_transport_get_frames_output(self.ptr)}
+    }
+
+    /// Frames read so far.
+    pub fn frames_input(&self) -> u64 {
+        unsafe {proton_sys::pn_transport_get_frames_input(self.ptr)}
+    }
+
     pub fn bind(&mut self, conn: &mut Connection) {
         unsafe {proton_sys::pn_transport_bind(self.ptr, conn.ptr)};
     }
@@ -365,49 +1146,771 @@ impl Transport {
         unsafe {proton_sys::pn_transport_unbind(self.ptr)};
     }
 
-    pub fn close_head(&mut self) {
-        unsafe {proton_sys::pn_transport_close_head(self.ptr)};
+    // turns a negative return value of pn_transport_* into an error
+    fn check(&self, code: proton_sys::ssize_t) -> Result<usize> {
+        if code < 0 {
+            Err(Error::from_transport(code as i32, self.ptr))
+        } else {
+            Ok(code as usize)
+        }
     }
 
-    pub fn close_tail(&mut self) {
-        unsafe {proton_sys::pn_transport_close_tail(self.ptr)};
+    /// Tells the transport nothing more will be read from it.
+    pub fn close_head(&mut self) -> Result<()> {
+        let code = unsafe {proton_sys::pn_transport_close_head(self.ptr)};
+        self.check(code as proton_sys::ssize_t).map(|_| ())
     }
 
-    pub fn has_capacity(&mut self) -> bool {
-        debug!("CAPACITY: {}", self.capacity());
-        self.capacity() > 0
+    /// Tells the transport nothing more will be written to it, e.g. once
+    /// the socket reached EOF.
+    pub fn close_tail(&mut self) -> Result<()> {
+        let code = unsafe {proton_sys::pn_transport_close_tail(self.ptr)};
+        self.check(code as proton_sys::ssize_t).map(|_| ())
     }
 
-    pub fn capacity(&mut self) -> i64 {
-        unsafe {proton_sys::pn_transport_capacity(self.ptr)}
+    /// Whether there's room to push more bytes. Fails like `capacity`.
+    pub fn has_capacity(&mut self) -> Result<bool> {
+        self.capacity().map(|capacity| capacity > 0)
     }
 
-    pub fn pending(&mut self) -> i8 {
-        let pending = unsafe {proton_sys::pn_transport_pending(self.ptr) as i8};
+    /// How many bytes can be pushed. Fails with `ErrorKind::Eos` once the
+    /// tail is closed.
+    pub fn capacity(&mut self) -> Result<usize> {
+        let capacity = unsafe {proton_sys::pn_transport_capacity(self.ptr)};
+        self.check(capacity)
+    }
 
-        if pending >= proton_sys::PN_EOS {
-            pending
-        } else {
-            // needs check for errors instead of returning pending
-            pending
+    /// How many bytes are waiting to be written out. Fails with
+    /// `ErrorKind::Eos` once the head is closed and everything was written.
+    pub fn pending(&mut self) -> Result<usize> {
+        let pending = unsafe {proton_sys::pn_transport_pending(self.ptr)};
+        self.check(pending)
+    }
+
+    /// Processes `size` bytes already copied into the tail.
+    pub fn process(&mut self, size: usize) -> Result<()> {
+        let code = unsafe {proton_sys::pn_transport_process(self.ptr, size as proton_sys::size_t)};
+        self.check(code as proton_sys::ssize_t).map(|_| ())
+    }
+
+    /// Copies bytes read from the peer into the transport and processes
+    /// them. Returns how many were taken, which is fewer than given when
+    /// the transport lacks the capacity for all of them.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<usize> {
+        let pushed = unsafe {
+            proton_sys::pn_transport_push(self.ptr,
+                                          bytes.as_ptr() as *const ::libc::c_char,
+                                          bytes.len() as proton_sys::size_t)
+        };
+        self.check(pushed)
+    }
+
+    /// The free space at the end of the transport's input buffer, for
+    /// bytes read from the peer to go straight into. The transport can't
+    /// be used until the tail is dropped or processed.
+    pub fn tail(&mut self) -> Result<Tail> {
+        let capacity = try!(self.capacity());
+        let ptr = unsafe {proton_sys::pn_transport_tail(self.ptr)};
+        Ok(Tail {ptr: ptr as *mut u8, len: capacity, transport: self})
+    }
+
+    /// The bytes waiting to be written out, straight from the transport's
+    /// output buffer. The transport can't be used until the head is dropped
+    /// or popped.
+    pub fn head(&mut self) -> Result<Head> {
+        let pending = try!(self.pending());
+        let ptr = unsafe {proton_sys::pn_transport_head(self.ptr)};
+        Ok(Head {ptr: ptr as *const u8, len: pending, transport: self})
+    }
+
+    /// Copies the start of the pending output into `buf`, without removing
+    /// it, and returns how many bytes were copied.
+    pub fn peek(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = ::std::cmp::min(try!(self.pending()), buf.len());
+        let code = unsafe {
+            proton_sys::pn_transport_peek(self.ptr,
+                                          buf.as_mut_ptr() as *mut ::libc::c_char,
+                                          len as proton_sys::size_t)
+        };
+        self.check(code).map(|_| len)
+    }
+
+    /// Removes `size` bytes from the start of the pending output, once
+    /// they've been written out. Fails with `ErrorKind::Argument` if fewer
+    /// than `size` bytes are pending.
+    pub fn pop(&mut self, size: usize) -> Result<()> {
+        let pending = try!(self.pending());
+        if size > pending {
+            return Err(Error::new(ErrorKind::Argument, "popping more bytes than are pending"));
         }
+        unsafe {proton_sys::pn_transport_pop(self.ptr, size as proton_sys::size_t)};
+        Ok(())
     }
+}
 
-    pub fn push(&mut self, bytes: &[u8]) {
-        let s = CString::new(bytes).unwrap();
-        //debug!("len: {}", s.len() as proton_sys::size_t);
+/// Input buffer space borrowed from a transport, see `Transport::tail`.
+pub struct Tail<'a> {
+    ptr: *mut u8,
+    len: usize,
+    transport: &'a mut Transport
+}
 
-        unsafe {
-            let size = bytes.len() as proton_sys::size_t;
-            let res = proton_sys::pn_transport_push(self.ptr, s.as_ptr(), size);
-
-            debug!("RESULT: {}", res);
-            if res != size as i64{
-                // this shouldn't panic
-                panic!("OVERFLOW");
-            } else if res < 0 {
-                panic!("ERRROR");
+impl<'a> Tail<'a> {
+    /// Processes the first `size` bytes written to the tail.
+    ///
+    /// Panics if `size` is larger than the tail.
+    pub fn process(self, size: usize) -> Result<()> {
+        assert!(size <= self.len, "processing {} bytes of a {} byte tail", size, self.len);
+        self.transport.process(size)
+    }
+}
+
+impl<'a> Deref for Tail<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe {slice::from_raw_parts(self.ptr, self.len)}
+    }
+}
+
+impl<'a> DerefMut for Tail<'a> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        if self.len == 0 {
+            return &mut [];
+        }
+        unsafe {slice::from_raw_parts_mut(self.ptr, self.len)}
+    }
+}
+
+/// Pending output borrowed from a transport, see `Transport::head`.
+pub struct Head<'a> {
+    ptr: *const u8,
+    len: usize,
+    transport: &'a mut Transport
+}
+
+impl<'a> Head<'a> {
+    /// Removes the first `size` bytes, once they've been written out.
+    ///
+    /// Panics if `size` is larger than the head.
+    pub fn pop(self, size: usize) {
+        assert!(size <= self.len, "popping {} bytes of a {} byte head", size, self.len);
+        unsafe {proton_sys::pn_transport_pop(self.transport.ptr, size as proton_sys::size_t)};
+    }
+}
+
+impl<'a> Deref for Head<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe {slice::from_raw_parts(self.ptr, self.len)}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::value::Value;
+    use super::types::Symbol;
+
+    #[test]
+    fn test_state_bits() {
+        let bits = (proton_sys::LOCAL_ACTIVE | proton_sys::REMOTE_CLOSED).bits();
+        let state = EndpointState::from_bits(bits).unwrap();
+        assert_eq!(EndpointState::new(State::ACTIVE, State::CLOSED), state);
+        assert_eq!(bits, state.as_bits());
+        assert!(state.is_remote_closing());
+        assert!(state.is_remote_closed());
+        assert!(!state.is_active());
+    }
+
+    #[test]
+    fn test_invalid_state_bits() {
+        let both = (proton_sys::LOCAL_ACTIVE | proton_sys::LOCAL_CLOSED).bits();
+        assert_eq!(ErrorKind::Argument, EndpointState::from_bits(both).unwrap_err().kind());
+        assert_eq!(ErrorKind::Argument, EndpointState::from_bits(0x100).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_state_mask() {
+        let mask = EndpointState {local: Some(State::ACTIVE), remote: None};
+        assert_eq!(proton_sys::LOCAL_ACTIVE.bits(), mask.as_bits());
+        assert_eq!(mask, EndpointState::from_bits(mask.as_bits()).unwrap());
+        assert!(mask.matches(&EndpointState::new(State::ACTIVE, State::CLOSED)));
+        assert!(!mask.matches(&EndpointState::new(State::UNINIT, State::ACTIVE)));
+        assert!(ANY_STATE.matches(&EndpointState::new(State::CLOSED, State::CLOSED)));
+        assert_eq!(0, ANY_STATE.as_bits());
+    }
+
+    #[test]
+    fn test_endpoint_state() {
+        let mut connection = Connection::new();
+        assert_eq!(EndpointState::new(State::UNINIT, State::UNINIT), connection.state());
+        connection.open();
+        assert_eq!(EndpointState::new(State::ACTIVE, State::UNINIT), connection.state());
+        connection.close();
+        assert!(connection.state().is_local_closed());
+    }
+
+    /// Moves whatever each transport has to write over to the other one
+    /// until neither has anything left.
+    pub fn pump(a: &mut Transport, b: &mut Transport) {
+        fn copy(from: &mut Transport, to: &mut Transport) -> bool {
+            let head = match from.head() {
+                Ok(ref head) if head.is_empty() => return false,
+                Ok(head) => head,
+                Err(_) => return false
+            };
+            let pushed = to.push(&head).unwrap();
+            assert_eq!(head.len(), pushed);
+            head.pop(pushed);
+            true
+        }
+
+        while copy(a, b) | copy(b, a) {}
+    }
+
+    /// A client and a server connection, each bound to its own transport.
+    pub struct Peers {
+        pub client: Connection,
+        pub server: Connection,
+        pub client_transport: Transport,
+        pub server_transport: Transport
+    }
+
+    impl Peers {
+        /// Lets `setup` configure either end before the transports are
+        /// bound, then exchanges whatever frames that leads to.
+        pub fn new<F: FnOnce(&mut Peers)>(setup: F) -> Peers {
+            let mut peers = Peers {
+                client: Connection::new(),
+                server: Connection::new(),
+                client_transport: Transport::client(),
+                server_transport: Transport::server()
+            };
+            setup(&mut peers);
+            peers.client_transport.bind(&mut peers.client);
+            peers.server_transport.bind(&mut peers.server);
+            peers.pump();
+            peers
+        }
+
+        pub fn pump(&mut self) {
+            pump(&mut self.client_transport, &mut self.server_transport);
+        }
+    }
+
+    #[test]
+    fn test_push_binary() {
+        let mut client = Connection::new();
+        client.open();
+        let mut client_transport = Transport::client();
+        client_transport.bind(&mut client);
+
+        let mut server = Connection::new();
+        let mut transport = Transport::server();
+        transport.bind(&mut server);
+        let head = client_transport.head().unwrap();
+        // the AMQP header alone has a 0 byte
+        assert!(head.contains(&0));
+        assert_eq!(Ok(head.len()), transport.push(&head));
+        assert_eq!(1, transport.frames_input());
+    }
+
+    #[test]
+    fn test_head_and_tail() {
+        let mut client = Connection::new();
+        client.open();
+        let mut client_transport = Transport::client();
+        client_transport.bind(&mut client);
+        let mut server = Connection::new();
+        let mut server_transport = Transport::server();
+        server_transport.bind(&mut server);
+
+        let mut header = [0; 8];
+        assert_eq!(Ok(8), client_transport.peek(&mut header));
+        assert_eq!(b"AMQP\x00\x01\x00\x00", &header);
+
+        // hand the output over in two goes, straight from one buffer to
+        // the other
+        let pending = client_transport.pending().unwrap();
+        for &size in [8, pending - 8].iter() {
+            {
+                let head = client_transport.head().unwrap();
+                let mut tail = server_transport.tail().unwrap();
+                assert!(tail.len() >= size);
+                for (to, from) in tail.iter_mut().zip(head[..size].iter()) {
+                    *to = *from;
+                }
+                tail.process(size).unwrap();
+                head.pop(size);
             }
+        }
+
+        assert_eq!(Ok(0), client_transport.pending());
+        assert!(server.state().is_remote_opening());
+    }
+
+    #[test]
+    fn test_pop_too_much() {
+        let mut transport = Transport::client();
+        let pending = transport.pending().unwrap();
+        assert_eq!(ErrorKind::Argument, transport.pop(pending + 1).unwrap_err().kind());
+        assert_eq!(Ok(pending), transport.pending());
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        let mut transport = Transport::server();
+        let error = match transport.push(b"GET / HTTP/1.1\r\n\r\n") {
+            Err(error) => error,
+            Ok(_) => transport.capacity().unwrap_err()
+        };
+        assert_eq!("amqp:connection:framing-error", error.condition().unwrap().name);
+    }
+
+    #[test]
+    fn test_close() {
+        let mut transport = Transport::server();
+        transport.close_tail().unwrap();
+        assert_eq!(ErrorKind::Eos, transport.capacity().unwrap_err().kind());
+        assert_eq!(ErrorKind::Eos, transport.has_capacity().unwrap_err().kind());
+        transport.close_head().unwrap();
+        assert_eq!(ErrorKind::Eos, transport.pending().unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_tick() {
+        let mut peers = Peers::new(|peers| {
+            peers.client.open();
+            peers.server.open();
+            peers.client_transport.set_idle_timeout(1000);
+        });
+        assert!(peers.server_transport.remote_idle_timeout() > 0);
+
+        // the server has to send something before the client gives up
+        let keepalive = peers.server_transport.tick(1000).unwrap();
+        assert!(keepalive > 1000 && keepalive < 2000);
+        assert_eq!(Ok(0), peers.server_transport.pending());
+        peers.server_transport.tick(keepalive);
+        assert!(peers.server_transport.pending().unwrap() > 0);
+
+        // but it doesn't, so the client does
+        assert_eq!(Some(2000), peers.client_transport.tick(1000));
+        peers.client_transport.tick(2000);
+        let error = peers.client_transport.capacity().unwrap_err();
+        assert_eq!(ErrorKind::Eos, error.kind());
+        assert_eq!("amqp:resource-limit-exceeded", error.condition().unwrap().name);
+        assert!(peers.client_transport.pending().unwrap() > 0);
+        assert!(!peers.client_transport.closed());
+    }
+
+    #[test]
+    fn test_transport_settings() {
+        let peers = Peers::new(|peers| {
+            peers.client.open();
+            peers.server.open();
+
+            let client_transport = &mut peers.client_transport;
+            client_transport.set_max_frame(4096);
+            client_transport.set_channel_max(7).unwrap();
+            client_transport.set_idle_timeout(30000);
+            assert_eq!(4096, client_transport.max_frame());
+            assert_eq!(7, client_transport.channel_max());
+            assert_eq!(30000, client_transport.idle_timeout());
+            assert_eq!(0, client_transport.frames_output());
+
+            peers.server_transport.set_max_frame(8192);
+        });
+
+        assert_eq!(4096, peers.server_transport.remote_max_frame());
+        assert_eq!(7, peers.server_transport.remote_channel_max());
+        assert_eq!(30000, peers.server_transport.remote_idle_timeout());
+        assert_eq!(8192, peers.client_transport.remote_max_frame());
+        assert_eq!(1, peers.client_transport.frames_output());
+        assert_eq!(1, peers.server_transport.frames_input());
+        assert!(peers.server.state().is_active());
+    }
+
+    #[test]
+    fn test_connection_settings() {
+        let mut connection = Connection::new();
+        connection.set_container("broker-1").unwrap();
+        connection.set_hostname("amqp.example.com").unwrap();
+        connection.set_user("guest").unwrap();
+        connection.set_password("secret").unwrap();
+        connection.set_offered_capabilities(&[Symbol::from("ANONYMOUS-RELAY")]);
+        connection.properties_mut().insert("product", Value::String("rust-proton".to_string())).unwrap();
+
+        assert_eq!(Some("broker-1"), connection.container());
+        assert_eq!(Some("amqp.example.com"), connection.hostname());
+        assert_eq!(Some("guest"), connection.user());
+        assert_eq!(vec![Symbol::from("ANONYMOUS-RELAY")], connection.offered_capabilities());
+        assert!(connection.desired_capabilities().is_empty());
+        assert_eq!(Some(Value::String("rust-proton".to_string())),
+                   connection.properties().get("product"));
+    }
+
+    #[test]
+    fn test_remote_open() {
+        let peers = Peers::new(|peers| {
+            let client = &mut peers.client;
+            client.set_container("client-1").unwrap();
+            client.set_hostname("amqp.example.com").unwrap();
+            client.set_desired_capabilities(&[Symbol::from("ANONYMOUS-RELAY")]);
+            client.properties_mut().insert("region", Value::String("eu".to_string())).unwrap();
+            client.open();
+        });
+
+        let server = &peers.server;
+        assert!(server.state().is_remote_opening());
+        assert_eq!(Some("client-1"), server.remote_container());
+        assert_eq!(Some("amqp.example.com"), server.remote_hostname());
+        assert_eq!(vec![Symbol::from("ANONYMOUS-RELAY")], server.remote_desired_capabilities());
+        assert!(server.remote_offered_capabilities().is_empty());
+        assert_eq!(Some(Value::String("eu".to_string())),
+                   server.remote_properties().get("region"));
+    }
+
+    #[test]
+    fn test_open_remotely_opened_endpoints() {
+        let mut peers = Peers::new(|peers| {
+            peers.client.open();
+            let mut session = peers.client.session();
+            session.open();
+            let mut sender = session.sender("orders").unwrap();
+            sender.open();
+            let mut receiver = session.receiver("replies").unwrap();
+            receiver.open();
+        });
+
+        let server = &mut peers.server;
+        let opening = EndpointState::new(State::UNINIT, State::ACTIVE);
+        for mut session in server.sessions(&opening) {
+            session.open();
+        }
+        let links: Vec<Link> = server.links(&opening).collect();
+        assert_eq!(2, links.len());
+        for mut link in links {
+            // the peer's sender is our receiver
+            match link {
+                Link::Receiver(_) => assert_eq!("orders", link.name()),
+                Link::Sender(_) => assert_eq!("replies", link.name()),
+            }
+            link.open();
+        }
+
+        assert_eq!(0, server.sessions(&opening).count());
+        assert_eq!(0, server.links(&opening).count());
+        assert_eq!(1, server.sessions(&ANY_STATE).count());
+        let active = EndpointState {local: Some(State::ACTIVE), remote: None};
+        assert_eq!(2, server.links(&active).count());
+    }
+
+    /// A client connection with an open sender and the server side of it,
+    /// with the receiver open and given credit.
+    pub struct Pair {
+        pub peers: Peers,
+        pub sender: Link,
+        pub receiver: Link
+    }
+
+    impl Pair {
+        pub fn new() -> Pair {
+            Pair::with_sender(|_| {})
+        }
+
+        /// Lets `setup` configure the sender before it's opened.
+        pub fn with_sender<F: FnOnce(&mut Link)>(setup: F) -> Pair {
+            let mut sender = None;
+            let mut peers = Peers::new(|peers| {
+                peers.client.open();
+                let mut session = peers.client.session();
+                session.open();
+                let mut link = session.sender("orders").unwrap();
+                setup(&mut link);
+                link.open();
+                sender = Some(link);
+            });
+
+            for mut session in peers.server.sessions(&ANY_STATE) {
+                session.open();
+            }
+            let mut receiver = peers.server.links(&ANY_STATE).next().unwrap();
+            receiver.open();
+            match receiver {
+                Link::Receiver(ref mut receiver) => receiver.flow(10),
+                _ => unreachable!()
+            }
+            peers.pump();
+
+            Pair {
+                peers: peers,
+                sender: sender.unwrap(),
+                receiver: receiver
+            }
+        }
+
+        pub fn pump(&mut self) {
+            self.peers.pump();
+        }
+
+        /// Sends `body` in a delivery tagged `tag`, and returns it along
+        /// with the server's side of it.
+        pub fn transfer(&mut self, tag: &[u8], body: &[u8]) -> (Delivery, Delivery) {
+            let outgoing = Delivery::new(&mut self.sender, tag);
+            match self.sender {
+                Link::Sender(ref mut sender) => assert_eq!(body.len(), sender.send(body).unwrap()),
+                _ => unreachable!()
+            }
+            self.sender.advance();
+            self.pump();
+
+            let incoming = self.receiver.current().unwrap();
+            self.receiver.advance();
+            (outgoing, incoming)
+        }
+    }
+
+    #[test]
+    fn test_send_and_recv_message() {
+        let mut pair = Pair::new();
+        let mut message = Message::new();
+        message.set_body(&Value::Binary(vec![0, 1, 0, 2])).unwrap();
+        let bytes = message.encode().unwrap();
+        let (outgoing, _) = pair.transfer(b"1", &bytes);
+        assert_eq!(b"1", outgoing.tag());
+
+        // transfer already moved the receiver past it, so look it up again
+        let mut receiver = match pair.receiver {
+            Link::Receiver(ref receiver) => receiver.clone(),
+            _ => unreachable!()
+        };
+        assert!(receiver.recv_message().unwrap().is_none());
+
+        let mut outgoing = Delivery::new(&mut pair.sender, b"2");
+        match pair.sender {
+            Link::Sender(ref mut sender) => assert_eq!(bytes.len(), sender.send(&bytes).unwrap()),
+            _ => unreachable!()
+        }
+        pair.sender.advance();
+        pair.pump();
+
+        let (mut delivery, received) = receiver.recv_message().unwrap().unwrap();
+        assert_eq!(b"2", delivery.tag());
+        assert_eq!(Value::Binary(vec![0, 1, 0, 2]), received.unwrap().body::<Value>().unwrap());
+        delivery.accept();
+        delivery.settle();
+        pair.pump();
+        assert_eq!(Some(Outcome::Accepted), outgoing.remote_outcome());
+        outgoing.settle();
+    }
+
+    #[test]
+    fn test_recv_undecodable_message() {
+        let mut pair = Pair::new();
+        let mut receiver = match pair.receiver {
+            Link::Receiver(ref receiver) => receiver.clone(),
+            _ => unreachable!()
+        };
+
+        let mut outgoing = Delivery::new(&mut pair.sender, b"1");
+        match pair.sender {
+            Link::Sender(ref mut sender) => sender.send(&[0xff, 0xff]).unwrap(),
+            _ => unreachable!()
+        };
+        pair.sender.advance();
+        pair.pump();
+
+        let (mut delivery, received) = receiver.recv_message().unwrap().unwrap();
+        assert_eq!(b"1", delivery.tag());
+        assert!(received.is_err());
+        assert!(pair.receiver.current().is_none());
+
+        let condition = Condition::new("amqp:decode-error", "not a message");
+        delivery.reject(&condition).unwrap();
+        delivery.settle();
+        pair.pump();
+        assert_eq!(Some(Outcome::Rejected(Some(condition))), outgoing.remote_outcome());
+        outgoing.settle();
+    }
+
+    #[test]
+    fn test_recv_in_pieces() {
+        let mut pair = Pair::new();
+        let mut outgoing = Delivery::new(&mut pair.sender, b"1");
+        match pair.sender {
+            Link::Sender(ref mut sender) => sender.send(b"hello world").unwrap(),
+            _ => unreachable!()
+        };
+        pair.sender.advance();
+        pair.pump();
+
+        match pair.receiver {
+            Link::Receiver(ref mut receiver) => {
+                let mut buf = [0; 8];
+                assert_eq!(Ok(Some(8)), receiver.recv(&mut buf));
+                assert_eq!(b"hello wo", &buf);
+                assert_eq!(Ok(Some(3)), receiver.recv(&mut buf));
+                assert_eq!(Ok(None), receiver.recv(&mut buf));
+            },
+            _ => unreachable!()
+        }
+        outgoing.settle();
+    }
+
+    #[test]
+    fn test_send_without_delivery() {
+        let mut connection = Connection::new();
+        match connection.session().sender("orders").unwrap() {
+            Link::Sender(mut sender) => {
+                let error = sender.send(b"\0data").unwrap_err();
+                // there's no current delivery to add to
+                assert_eq!(ErrorKind::Eos, error.kind());
+            },
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_link_name_with_nul() {
+        let mut connection = Connection::new();
+        let mut session = connection.session();
+        assert_eq!(Some(ErrorKind::Argument), session.sender("orders\0eu").err().map(|e| e.kind()));
+        assert_eq!(Some(ErrorKind::Argument), session.receiver("\0").err().map(|e| e.kind()));
+    }
+
+    #[test]
+    fn test_settle_modes() {
+        let mut pair = Pair::with_sender(|sender| {
+            assert_eq!(SndSettleMode::Mixed, sender.snd_settle_mode());
+            assert_eq!(RcvSettleMode::First, sender.rcv_settle_mode());
+            sender.set_snd_settle_mode(SndSettleMode::Settled);
+            sender.set_rcv_settle_mode(RcvSettleMode::Second);
+        });
+
+        assert_eq!(SndSettleMode::Settled, pair.receiver.remote_snd_settle_mode());
+        assert_eq!(RcvSettleMode::Second, pair.receiver.remote_rcv_settle_mode());
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut pair = Pair::new();
+        assert_eq!(10, pair.sender.credit());
+        assert!(!pair.sender.drain_mode());
+
+        match pair.receiver {
+            Link::Receiver(ref mut receiver) => {
+                receiver.drain(0);
+                assert!(receiver.draining());
+            },
+            _ => unreachable!()
+        }
+        pair.pump();
+
+        // nothing to send, so the sender gives all of its credit back
+        assert!(pair.sender.drain_mode());
+        assert_eq!(10, pair.sender.drained());
+        assert_eq!(0, pair.sender.credit());
+        pair.pump();
+
+        assert_eq!(0, pair.receiver.credit());
+        match pair.receiver {
+            Link::Receiver(ref mut receiver) => assert!(!receiver.draining()),
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_unsettled_deliveries() {
+        let mut pair = Pair::new();
+        let (_, mut first) = pair.transfer(b"1", b"a");
+        pair.transfer(b"2", b"b");
+        pair.transfer(b"3", b"c");
+        first.accept();
+        first.settle();
+        pair.pump();
+
+        assert_eq!(3, pair.sender.unsettled());
+        let tags: Vec<Vec<u8>> = pair.sender.unsettled_deliveries().map(|d| d.tag().to_vec()).collect();
+        assert_eq!(vec![b"1".to_vec(), b"2".to_vec(), b"3".to_vec()], tags);
+        assert_eq!(2, pair.receiver.unsettled());
+    }
+
+    fn refcount<T>(ptr: *mut T) -> i32 {
+        unsafe {proton_sys::pn_refcount(ptr as *mut ::libc::c_void)}
+    }
+
+    #[test]
+    fn test_clone_takes_a_reference() {
+        let connection = Connection::new();
+        assert_eq!(1, refcount(connection.as_ptr()));
+
+        let other = connection.clone();
+        assert_eq!(connection.as_ptr(), other.as_ptr());
+        assert_eq!(2, refcount(connection.as_ptr()));
+
+        drop(other);
+        assert_eq!(1, refcount(connection.as_ptr()));
+    }
+
+    #[test]
+    fn test_parent_handles_are_shared() {
+        let mut connection = Connection::new();
+        let mut session = connection.session();
+        let before = refcount(connection.as_ptr());
+        {
+            let parent = session.connection();
+            assert_eq!(connection.as_ptr(), parent.as_ptr());
+            assert_eq!(before + 1, refcount(connection.as_ptr()));
+        }
+        assert_eq!(before, refcount(connection.as_ptr()));
+    }
+
+    #[test]
+    fn test_session_outlives_release() {
+        let mut connection = Connection::new();
+        let mut session = connection.session();
+        session.open();
+        connection.release();
+
+        // still ours to look at
+        assert_eq!(Some(State::ACTIVE), session.state().local);
+    }
+
+    #[test]
+    fn test_close_with_condition() {
+        let mut connection = Connection::new();
+        assert_eq!(None, connection.condition());
+        assert_eq!(None, connection.remote_condition());
+
+        let mut condition = Condition::new("amqp:resource-limit-exceeded", "too many sessions");
+        condition.info = Some(Value::Map(vec![(Value::Symbol("limit".to_string()), Value::Uint(10))]));
+        connection.open();
+        let invalid = Condition::new("amqp:internal-error", "bad\0description");
+        assert_eq!(ErrorKind::Argument, connection.close_with(&invalid).unwrap_err().kind());
+        assert_eq!(Some(State::ACTIVE), connection.state().local);
+        connection.close_with(&condition).unwrap();
+
+        assert_eq!(Some(condition), connection.condition());
+        assert_eq!(None, connection.remote_condition());
+    }
+
+    #[test]
+    fn test_condition_without_description() {
+        let mut connection = Connection::new();
+        let condition = Condition {
+            name: "amqp:connection:forced".to_string(),
+            description: None,
+            info: None
         };
+        connection.set_condition(&condition).unwrap();
+        assert_eq!(Some(condition), connection.condition());
     }
 }
@@ -0,0 +1,349 @@
+use std::marker::PhantomData;
+use std::ops::Deref;
+use proton_sys;
+
+use super::{symbols, set_symbols, to_str, c_string};
+use super::data::{DataMap, DataMapMut};
+use super::error::Error;
+use super::types::Symbol;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TerminusType {
+    Unspecified,
+    Source,
+    Target,
+    /// transaction coordinator
+    Coordinator,
+    /// a code proton doesn't define, as sent by the peer
+    Other(u32),
+}
+
+/// What the terminus keeps across detaches.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Durability {
+    Nondurable,
+    /// its configuration, but not its unsettled state
+    Configuration,
+    /// its configuration and unsettled state
+    Deliveries,
+    /// a code proton doesn't define, as sent by the peer
+    Other(u32),
+}
+
+/// When a terminus that isn't durable goes away.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExpiryPolicy {
+    LinkDetach,
+    SessionEnd,
+    ConnectionClose,
+    Never,
+    /// a code proton doesn't define, as sent by the peer
+    Other(u32),
+}
+
+/// Whether a source hands each message to one receiver or to all of them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DistributionMode {
+    Unspecified,
+    Copy,
+    Move,
+    /// a code proton doesn't define, as sent by the peer
+    Other(u32),
+}
+
+// Conversions between the enums above and proton's constants. Codes come
+// from the peer's attach, so unknown ones are kept rather than refused.
+macro_rules! terminus_enum {
+    ($t:ident, $($variant:ident => $code:ident),+) => (
+        impl $t {
+            fn from_raw(code: ::libc::c_uint) -> $t {
+                match code {
+                    $(proton_sys::$code => $t::$variant,)+
+                    code => $t::Other(code as u32)
+                }
+            }
+
+            fn as_raw(&self) -> ::libc::c_uint {
+                match *self {
+                    $($t::$variant => proton_sys::$code,)+
+                    $t::Other(code) => code as ::libc::c_uint
+                }
+            }
+        }
+    )
+}
+
+terminus_enum!(TerminusType,
+               Unspecified => PN_UNSPECIFIED,
+               Source => PN_SOURCE,
+               Target => PN_TARGET,
+               Coordinator => PN_COORDINATOR);
+terminus_enum!(Durability,
+               Nondurable => PN_NONDURABLE,
+               Configuration => PN_CONFIGURATION,
+               Deliveries => PN_DELIVERIES);
+terminus_enum!(ExpiryPolicy,
+               LinkDetach => PN_EXPIRE_WITH_LINK,
+               SessionEnd => PN_EXPIRE_WITH_SESSION,
+               ConnectionClose => PN_EXPIRE_WITH_CONNECTION,
+               Never => PN_EXPIRE_NEVER);
+terminus_enum!(DistributionMode,
+               Unspecified => PN_DIST_MODE_UNSPECIFIED,
+               Copy => PN_DIST_MODE_COPY,
+               Move => PN_DIST_MODE_MOVE);
+
+/// Source or target of a link, borrowed from the link. Only the link's own
+/// termini can be changed, through `TerminusMut`, not the peer's:
+///
+/// ```compile_fail
+/// let mut connection = rust_proton::Connection::new();
+/// let link = connection.session().sender("orders").unwrap();
+/// link.remote_source().set_dynamic(true);
+/// ```
+pub struct Terminus<'a> {
+    ptr: *mut proton_sys::pn_terminus_t,
+    __phantom: PhantomData<&'a ()>
+}
+
+/// A terminus that can be changed, e.g. before the link is opened.
+pub struct TerminusMut<'a> {
+    terminus: Terminus<'a>
+}
+
+impl<'a> Terminus<'a> {
+    /// Unsafe because the link holding the terminus must outlive `'a`.
+    pub unsafe fn new(ptr: *mut proton_sys::pn_terminus_t) -> Terminus<'a> {
+        Terminus {ptr: ptr, __phantom: PhantomData}
+    }
+
+    pub fn kind(&self) -> TerminusType {
+        TerminusType::from_raw(unsafe {proton_sys::pn_terminus_get_type(self.ptr)})
+    }
+
+    /// Address of the node, e.g. a queue name. `None` for a dynamic
+    /// terminus until the peer has picked one.
+    pub fn address(&self) -> Option<&str> {
+        unsafe {to_str(proton_sys::pn_terminus_get_address(self.ptr))}
+    }
+
+    pub fn durability(&self) -> Durability {
+        Durability::from_raw(unsafe {proton_sys::pn_terminus_get_durability(self.ptr)})
+    }
+
+    pub fn expiry_policy(&self) -> ExpiryPolicy {
+        ExpiryPolicy::from_raw(unsafe {proton_sys::pn_terminus_get_expiry_policy(self.ptr)})
+    }
+
+    /// Seconds the terminus is kept around once its expiry policy applies.
+    pub fn timeout(&self) -> u32 {
+        unsafe {proton_sys::pn_terminus_get_timeout(self.ptr)}
+    }
+
+    /// Whether the peer is asked to create the node, e.g. a temporary
+    /// reply queue.
+    pub fn is_dynamic(&self) -> bool {
+        unsafe {proton_sys::pn_terminus_is_dynamic(self.ptr) != 0}
+    }
+
+    pub fn distribution_mode(&self) -> DistributionMode {
+        let mode = unsafe {proton_sys::pn_terminus_get_distribution_mode(self.ptr)};
+        DistributionMode::from_raw(mode)
+    }
+
+    /// Properties of a dynamically created node.
+    pub fn properties(&self) -> DataMap<Symbol> {
+        unsafe {DataMap::new(proton_sys::pn_terminus_properties(self.ptr))}
+    }
+
+    pub fn capabilities(&self) -> Vec<Symbol> {
+        symbols(unsafe {proton_sys::pn_terminus_capabilities(self.ptr)})
+    }
+
+    /// Outcomes a source supports, e.g. `amqp:accepted:list`.
+    pub fn outcomes(&self) -> Vec<Symbol> {
+        symbols(unsafe {proton_sys::pn_terminus_outcomes(self.ptr)})
+    }
+
+    /// Filters of a source, keyed by name.
+    pub fn filter(&self) -> DataMap<Symbol> {
+        unsafe {DataMap::new(proton_sys::pn_terminus_filter(self.ptr))}
+    }
+}
+
+impl<'a> TerminusMut<'a> {
+    /// Unsafe because the link holding the terminus must outlive `'a` and
+    /// nothing else may change it meanwhile.
+    pub unsafe fn new(ptr: *mut proton_sys::pn_terminus_t) -> TerminusMut<'a> {
+        TerminusMut {terminus: Terminus::new(ptr)}
+    }
+
+    pub fn set_kind(&mut self, kind: TerminusType) {
+        unsafe {proton_sys::pn_terminus_set_type(self.terminus.ptr, kind.as_raw())};
+    }
+
+    pub fn set_address(&mut self, address: Option<&str>) -> Result<(), Error> {
+        let address = match address {
+            Some(address) => Some(try!(c_string(address))),
+            None => None
+        };
+        let ptr = address.as_ref().map_or(::std::ptr::null(), |a| a.as_ptr());
+        unsafe {proton_sys::pn_terminus_set_address(self.terminus.ptr, ptr)};
+        Ok(())
+    }
+
+    pub fn set_durability(&mut self, durability: Durability) {
+        unsafe {proton_sys::pn_terminus_set_durability(self.terminus.ptr, durability.as_raw())};
+    }
+
+    pub fn set_expiry_policy(&mut self, policy: ExpiryPolicy) {
+        unsafe {proton_sys::pn_terminus_set_expiry_policy(self.terminus.ptr, policy.as_raw())};
+    }
+
+    pub fn set_timeout(&mut self, seconds: u32) {
+        unsafe {proton_sys::pn_terminus_set_timeout(self.terminus.ptr, seconds)};
+    }
+
+    pub fn set_dynamic(&mut self, dynamic: bool) {
+        unsafe {proton_sys::pn_terminus_set_dynamic(self.terminus.ptr, dynamic as u8)};
+    }
+
+    pub fn set_distribution_mode(&mut self, mode: DistributionMode) {
+        unsafe {proton_sys::pn_terminus_set_distribution_mode(self.terminus.ptr, mode.as_raw())};
+    }
+
+    pub fn properties_mut(&mut self) -> DataMapMut<Symbol> {
+        unsafe {DataMapMut::new(proton_sys::pn_terminus_properties(self.terminus.ptr))}
+    }
+
+    pub fn set_capabilities(&mut self, capabilities: &[Symbol]) {
+        set_symbols(unsafe {proton_sys::pn_terminus_capabilities(self.terminus.ptr)}, capabilities);
+    }
+
+    pub fn set_outcomes(&mut self, outcomes: &[Symbol]) {
+        set_symbols(unsafe {proton_sys::pn_terminus_outcomes(self.terminus.ptr)}, outcomes);
+    }
+
+    pub fn filter_mut(&mut self) -> DataMapMut<Symbol> {
+        unsafe {DataMapMut::new(proton_sys::pn_terminus_filter(self.terminus.ptr))}
+    }
+
+    /// Makes this terminus a copy of `other`, e.g. to answer an attach with
+    /// the source the peer asked for.
+    pub fn copy_from(&mut self, other: &Terminus) -> Result<(), Error> {
+        match unsafe {proton_sys::pn_terminus_copy(self.terminus.ptr, other.ptr)} {
+            0 => Ok(()),
+            code => Err(Error::from_code(code))
+        }
+    }
+}
+
+impl<'a> Deref for TerminusMut<'a> {
+    type Target = Terminus<'a>;
+
+    fn deref(&self) -> &Terminus<'a> {
+        &self.terminus
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Connection, Link, Endpoint, ANY_STATE};
+    use super::super::tests::Peers;
+    use super::super::types::Symbol;
+    use super::super::value::Value;
+
+    fn sender(connection: &mut Connection) -> Link {
+        connection.session().sender("orders").unwrap()
+    }
+
+    #[test]
+    fn test_settings() {
+        let mut connection = Connection::new();
+        let mut link = sender(&mut connection);
+        let mut target = link.target_mut();
+        assert_eq!(TerminusType::Target, target.kind());
+        assert_eq!(Durability::Nondurable, target.durability());
+        assert_eq!(ExpiryPolicy::SessionEnd, target.expiry_policy());
+
+        target.set_address(Some("orders.eu")).unwrap();
+        target.set_durability(Durability::Deliveries);
+        target.set_expiry_policy(ExpiryPolicy::Never);
+        target.set_timeout(60);
+        target.set_capabilities(&[Symbol::from("queue")]);
+
+        assert_eq!(Some("orders.eu"), target.address());
+        assert_eq!(Durability::Deliveries, target.durability());
+        assert_eq!(ExpiryPolicy::Never, target.expiry_policy());
+        assert_eq!(60, target.timeout());
+        assert_eq!(vec![Symbol::from("queue")], target.capabilities());
+
+        target.set_address(None).unwrap();
+        assert!(target.set_address(Some("orders\0eu")).is_err());
+        assert_eq!(None, target.address());
+    }
+
+    #[test]
+    fn test_source_filter_and_outcomes() {
+        let mut connection = Connection::new();
+        let mut link = sender(&mut connection);
+        let mut source = link.source_mut();
+        source.set_distribution_mode(DistributionMode::Copy);
+        source.set_outcomes(&[Symbol::from("amqp:accepted:list"), Symbol::from("amqp:rejected:list")]);
+        source.filter_mut().insert("selector", Value::String("region = 'eu'".to_string())).unwrap();
+
+        assert_eq!(DistributionMode::Copy, source.distribution_mode());
+        assert_eq!(2, source.outcomes().len());
+        assert_eq!(Some(Value::String("region = 'eu'".to_string())), source.filter().get("selector"));
+    }
+
+    #[test]
+    fn test_unknown_codes() {
+        assert_eq!(Durability::Other(7), Durability::from_raw(7));
+        assert_eq!(7, Durability::Other(7).as_raw());
+        assert_eq!(ExpiryPolicy::Never, ExpiryPolicy::from_raw(ExpiryPolicy::Never.as_raw()));
+    }
+
+    #[test]
+    fn test_copy_from() {
+        let mut connection = Connection::new();
+        let mut from = sender(&mut connection);
+        from.source_mut().set_address(Some("orders")).unwrap();
+        from.source_mut().set_dynamic(true);
+
+        let mut to = sender(&mut connection);
+        to.source_mut().copy_from(&from.source()).unwrap();
+        assert_eq!(Some("orders"), to.source().address());
+        assert!(to.source().is_dynamic());
+    }
+
+    #[test]
+    fn test_answer_attach_with_remote_terminus() {
+        let mut peers = Peers::new(|peers| {
+            peers.client.open();
+            let mut session = peers.client.session();
+            session.open();
+            let mut receiver = session.receiver("replies").unwrap();
+            receiver.source_mut().set_dynamic(true);
+            receiver.source_mut().set_expiry_policy(ExpiryPolicy::SessionEnd);
+            receiver.target_mut().set_address(Some("client-1")).unwrap();
+            receiver.open();
+        });
+        let receiver = peers.client.links(&ANY_STATE).next().unwrap();
+
+        let mut link = peers.server.links(&ANY_STATE).next().unwrap();
+        assert!(link.remote_source().is_dynamic());
+        assert_eq!(None, link.remote_source().address());
+        assert_eq!(Some("client-1"), link.remote_target().address());
+
+        // a second handle, since the target is borrowed mutably
+        let attached = link.clone();
+        link.target_mut().copy_from(&attached.remote_target()).unwrap();
+        link.source_mut().set_address(Some("tmp.queue.1")).unwrap();
+        link.open();
+        peers.pump();
+
+        assert_eq!(Some("tmp.queue.1"), receiver.remote_source().address());
+        assert_eq!(Some("client-1"), receiver.remote_target().address());
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use super::{Delivery, Link};
+use super::error::{Error, ErrorKind, Result};
+
+struct Tracked<T> {
+    context: T,
+    sent_at: i64
+}
+
+/// Keeps some context of the caller's, such as the message to send again
+/// after a reconnect, for the deliveries a producer has sent on a link and
+/// the peer hasn't settled yet. Proton's list of unsettled deliveries is
+/// what's walked, so the tracker only maps tags to contexts.
+///
+/// Times are in milliseconds, on the clock given to `Transport::tick`.
+pub struct Tracker<T> {
+    link: Link,
+    entries: HashMap<Vec<u8>, Tracked<T>>
+}
+
+impl<T> Tracker<T> {
+    pub fn new(link: &Link) -> Tracker<T> {
+        Tracker {link: link.clone(), entries: HashMap::new()}
+    }
+
+    /// Starts tracking `delivery`, sent at `now`. Fails if it's on another
+    /// link, or its tag is already tracked.
+    pub fn track(&mut self, delivery: &Delivery, context: T, now: i64) -> Result<()> {
+        if delivery.link().as_ptr() != self.link.as_ptr() {
+            return Err(Error::new(ErrorKind::Argument, "delivery is on another link"));
+        }
+
+        let tag = delivery.tag().to_vec();
+        if self.entries.contains_key(&tag) {
+            return Err(Error::new(ErrorKind::Argument, "delivery tag is already tracked"));
+        }
+
+        self.entries.insert(tag, Tracked {context: context, sent_at: now});
+        Ok(())
+    }
+
+    /// Deliveries still waiting for the peer.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, tag: &[u8]) -> Option<&T> {
+        self.entries.get(tag).map(|e| &e.context)
+    }
+
+    /// Stops tracking the deliveries the peer has settled and returns them,
+    /// so their outcome can be looked at before settling them locally.
+    /// Deliveries settled locally behind the tracker's back are dropped.
+    pub fn take_settled(&mut self) -> Vec<(Delivery, T)> {
+        let mut settled = Vec::new();
+        let mut pending = HashMap::new();
+        for delivery in self.link.unsettled_deliveries() {
+            if let Some(entry) = self.entries.remove(delivery.tag()) {
+                if delivery.settled() {
+                    settled.push((delivery, entry.context));
+                } else {
+                    pending.insert(delivery.tag().to_vec(), entry);
+                }
+            }
+        }
+        self.entries = pending;
+        settled
+    }
+
+    /// How long the oldest delivery has been waiting.
+    pub fn oldest_age(&self, now: i64) -> Option<i64> {
+        self.entries.values().map(|e| e.sent_at).min().map(|sent_at| now - sent_at)
+    }
+
+    /// Deliveries waiting for longer than `age`, in the order they were
+    /// sent, with their contexts.
+    pub fn older_than(&self, now: i64, age: i64) -> Vec<(Delivery, &T)> {
+        let mut older = Vec::new();
+        for delivery in self.link.unsettled_deliveries() {
+            let context = match self.entries.get(delivery.tag()) {
+                Some(entry) if now - entry.sent_at > age => &entry.context,
+                _ => continue
+            };
+            older.push((delivery, context));
+        }
+        older
+    }
+
+    /// Stops tracking everything and returns the contexts in the order the
+    /// deliveries were sent, e.g. to send them again once reconnected.
+    pub fn reset(&mut self) -> Vec<T> {
+        let mut contexts = Vec::new();
+        for delivery in self.link.unsettled_deliveries() {
+            if let Some(entry) = self.entries.remove(delivery.tag()) {
+                contexts.push(entry.context);
+            }
+        }
+        self.entries.clear();
+        contexts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Outcome;
+    use super::super::tests::Pair;
+
+    #[test]
+    fn test_tracker() {
+        let mut pair = Pair::new();
+        let mut tracker = Tracker::new(&pair.sender);
+        let (outgoing, mut first) = pair.transfer(b"1", b"a");
+        tracker.track(&outgoing, "first", 1000).unwrap();
+        assert!(tracker.track(&outgoing, "again", 1000).is_err());
+        let (outgoing, _) = pair.transfer(b"2", b"b");
+        tracker.track(&outgoing, "second", 1500).unwrap();
+        let (outgoing, incoming) = pair.transfer(b"3", b"c");
+        tracker.track(&outgoing, "third", 1800).unwrap();
+        assert!(tracker.track(&incoming, "elsewhere", 1800).is_err());
+
+        assert_eq!(3, tracker.len());
+        assert_eq!(Some(&"second"), tracker.get(b"2"));
+        assert_eq!(Some(1000), tracker.oldest_age(2000));
+        let older: Vec<(Vec<u8>, &str)> = tracker.older_than(2000, 300).into_iter()
+            .map(|(delivery, context)| (delivery.tag().to_vec(), *context))
+            .collect();
+        assert_eq!(vec![(b"1".to_vec(), "first"), (b"2".to_vec(), "second")], older);
+
+        first.accept();
+        first.settle();
+        pair.pump();
+
+        let settled = tracker.take_settled();
+        assert_eq!(1, settled.len());
+        let (mut delivery, context) = settled.into_iter().next().unwrap();
+        assert_eq!("first", context);
+        assert_eq!(Some(Outcome::Accepted), delivery.remote_outcome());
+        delivery.settle();
+
+        assert_eq!(Some(500), tracker.oldest_age(2000));
+        assert_eq!(vec!["second", "third"], tracker.reset());
+        assert!(tracker.is_empty());
+    }
+}
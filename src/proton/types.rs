@@ -0,0 +1,213 @@
+//! Wrappers for the AMQP types that have no Rust counterpart.
+//!
+//! The Encoder and Decoder map each of these to the AMQP type of the same
+//! name, so `Symbol("x-opt-foo")` goes on the wire as a symbol rather than
+//! a string and `Binary(vec![..])` as binary rather than an array of
+//! ubytes.
+
+use std::ops::Deref;
+use std::string;
+use rustc_serialize::{Encodable, Decodable, Encoder, Decoder};
+
+use super::encoder::AMQP_TYPE;
+use super::value::{Value, read_16};
+
+fn encode_16<S: Encoder>(bytes: &[u8; 16], s: &mut S) -> Result<(), S::Error> {
+    bytes[..].encode(s)
+}
+
+macro_rules! amqp_type {
+    ($name:ident) => (
+        amqp_type!($name, Encodable::encode, Decodable::decode);
+    );
+    ($name:ident, bytes16) => (
+        amqp_type!($name, encode_16, read_16);
+    );
+    ($name:ident, $encode:expr, $decode:expr) => (
+        impl Encodable for $name {
+            fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+                s.emit_enum(AMQP_TYPE, |s| {
+                    s.emit_enum_variant(stringify!($name), 0, 1, |s| {
+                        s.emit_enum_variant_arg(0, |s| $encode(&self.0, s))
+                    })
+                })
+            }
+        }
+
+        impl Decodable for $name {
+            fn decode<D: Decoder>(d: &mut D) -> Result<$name, D::Error> {
+                d.read_enum(AMQP_TYPE, |d| {
+                    d.read_enum_variant(&[stringify!($name)], |d, _| {
+                        d.read_enum_variant_arg(0, $decode).map($name)
+                    })
+                })
+            }
+        }
+
+        impl From<$name> for Value {
+            fn from(v: $name) -> Value {
+                Value::$name(v.0)
+            }
+        }
+    )
+}
+
+/// An AMQP symbol: an ASCII string used for names and keys, such as
+/// annotation keys, capabilities and error conditions.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Symbol(pub string::String);
+
+/// An opaque sequence of bytes.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Binary(pub Vec<u8>);
+
+/// Milliseconds since the unix epoch.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Timestamp(pub i64);
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Uuid(pub [u8; 16]);
+
+/// IEEE 754 decimal32, kept as its raw bits.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Decimal32(pub u32);
+
+/// IEEE 754 decimal64, kept as its raw bits.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Decimal64(pub u64);
+
+/// IEEE 754 decimal128, kept as its raw bytes.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Decimal128(pub [u8; 16]);
+
+amqp_type!(Symbol);
+amqp_type!(Binary);
+amqp_type!(Timestamp);
+amqp_type!(Uuid, bytes16);
+amqp_type!(Decimal32);
+amqp_type!(Decimal64);
+amqp_type!(Decimal128, bytes16);
+
+impl<'a> From<&'a str> for Symbol {
+    fn from(s: &'a str) -> Symbol {
+        Symbol(s.to_string())
+    }
+}
+
+impl From<string::String> for Symbol {
+    fn from(s: string::String) -> Symbol {
+        Symbol(s)
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<'a> From<&'a [u8]> for Binary {
+    fn from(bytes: &'a [u8]) -> Binary {
+        Binary(bytes.to_vec())
+    }
+}
+
+impl From<Vec<u8>> for Binary {
+    fn from(bytes: Vec<u8>) -> Binary {
+        Binary(bytes)
+    }
+}
+
+impl Deref for Binary {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::encoder::encode;
+    use super::super::decoder::decode;
+    use proton_sys;
+
+    #[derive(RustcEncodable, RustcDecodable, PartialEq, Debug)]
+    struct Annotated {
+        key: Symbol,
+        payload: Binary,
+        sent: Timestamp,
+        id: Option<Uuid>,
+    }
+
+    /// Returns the type of the first value in `encoded`.
+    fn first_type(encoded: &[u8]) -> proton_sys::pn_type_t {
+        unsafe {
+            let data = proton_sys::pn_data(16);
+            proton_sys::pn_data_decode(data,
+                                       encoded.as_ptr() as *const ::libc::c_char,
+                                       encoded.len() as proton_sys::size_t);
+            proton_sys::pn_data_rewind(data);
+            proton_sys::pn_data_next(data);
+            let t = proton_sys::pn_data_type(data);
+            proton_sys::pn_data_free(data);
+            t
+        }
+    }
+
+    macro_rules! create_test {
+        ($name:ident, $value:expr, $t:ident) => (
+            #[test]
+            fn $name() {
+                let value = $value;
+                let encoded = encode(&value).unwrap();
+                assert_eq!(proton_sys::$t, first_type(&encoded));
+                assert_eq!(value, decode(&encoded).unwrap());
+            }
+        )
+    }
+
+    create_test!(test_symbol, Symbol::from("x-opt-jms-type"), PN_SYMBOL);
+    create_test!(test_binary, Binary(vec![0, 159, 146, 150]), PN_BINARY);
+    create_test!(test_empty_binary, Binary(vec![]), PN_BINARY);
+    create_test!(test_timestamp, Timestamp(1436000000000), PN_TIMESTAMP);
+    create_test!(test_uuid, Uuid([0xab; 16]), PN_UUID);
+    create_test!(test_decimal32, Decimal32(0x2200000a), PN_DECIMAL32);
+    create_test!(test_decimal64, Decimal64(0x223c00000000000a), PN_DECIMAL64);
+    create_test!(test_decimal128, Decimal128([1; 16]), PN_DECIMAL128);
+
+    #[test]
+    fn test_struct_fields() {
+        let value = Annotated {
+            key: Symbol::from("x-opt-partition-key"),
+            payload: Binary::from(&b"\x00\x01"[..]),
+            sent: Timestamp(0),
+            id: Some(Uuid([3; 16])),
+        };
+        let encoded = encode(&value).unwrap();
+        assert_eq!(value, decode(&encoded).unwrap());
+    }
+
+    #[test]
+    fn test_symbol_array() {
+        let value = vec![Symbol::from("ANONYMOUS-RELAY"), Symbol::from("DELAYED_DELIVERY")];
+        let encoded = encode(&value).unwrap();
+        assert_eq!(proton_sys::PN_ARRAY, first_type(&encoded));
+        assert_eq!(value, decode::<Vec<Symbol>>(&encoded).unwrap());
+    }
+
+    #[test]
+    fn test_string_is_not_symbol() {
+        let encoded = encode(&"x-opt-jms-type".to_string()).unwrap();
+        assert!(decode::<Symbol>(&encoded).is_err());
+    }
+
+    #[test]
+    fn test_into_value() {
+        assert_eq!(Value::Symbol("a".to_string()), Value::from(Symbol::from("a")));
+        assert_eq!(Value::Timestamp(7), Value::from(Timestamp(7)));
+    }
+}
@@ -0,0 +1,631 @@
+use std::char;
+use std::string;
+use rustc_serialize::{Encodable, Decodable, Encoder, Decoder};
+use proton_sys;
+
+use super::encoder::{AMQP_TYPE, pn_bytes};
+use super::decoder::{DecoderError, DecodeResult, bytes_as_slice};
+
+/// Variant names, in declaration order. They match the names the Encoder
+/// and Decoder use for the AMQP types.
+static VARIANTS: [&'static str; 25] = [
+    "Null", "Bool",
+    "Ubyte", "Ushort", "Uint", "Ulong",
+    "Byte", "Short", "Int", "Long",
+    "Float", "Double", "Char", "Timestamp",
+    "Decimal32", "Decimal64", "Decimal128", "Uuid",
+    "Binary", "String", "Symbol",
+    "List", "Map", "Array", "Described",
+];
+
+/// Any AMQP value, for data whose schema isn't known up front.
+///
+/// Maps keep their entries in order and may have keys of mixed types, so
+/// they're kept as a list of pairs. Array elements must all be of the same
+/// AMQP type; a described array is an array whose elements are all
+/// `Described` with the same descriptor.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Ubyte(u8),
+    Ushort(u16),
+    Uint(u32),
+    Ulong(u64),
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Char(char),
+    /// milliseconds since the unix epoch
+    Timestamp(i64),
+    Decimal32(u32),
+    Decimal64(u64),
+    Decimal128([u8; 16]),
+    Uuid([u8; 16]),
+    Binary(Vec<u8>),
+    String(string::String),
+    Symbol(string::String),
+    List(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Array(Vec<Value>),
+    /// (descriptor, value)
+    Described(Box<Value>, Box<Value>),
+}
+
+fn to_c_bytes(bytes: &[u8; 16]) -> [::libc::c_char; 16] {
+    let mut c_bytes = [0; 16];
+    for (dst, src) in c_bytes.iter_mut().zip(bytes.iter()) {
+        *dst = *src as ::libc::c_char;
+    }
+    c_bytes
+}
+
+fn from_c_bytes(c_bytes: &[::libc::c_char; 16]) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    for (dst, src) in bytes.iter_mut().zip(c_bytes.iter()) {
+        *dst = *src as u8;
+    }
+    bytes
+}
+
+fn to_string(bytes: proton_sys::pn_bytes_t) -> DecodeResult<string::String> {
+    match string::String::from_utf8(unsafe{bytes_as_slice(bytes)}.to_vec()) {
+        Ok(s) => Ok(s),
+        Err(e) => Err(DecoderError::ExpectedError("utf-8".to_string(), e.to_string()))
+    }
+}
+
+/// Reads the children of the compound value the cursor is on.
+fn get_children(data: *mut proton_sys::pn_data_t) -> DecodeResult<Vec<Value>> {
+    let mut children = Vec::new();
+    let mut result = Ok(());
+
+    unsafe {
+        proton_sys::pn_data_enter(data);
+        while proton_sys::pn_data_next(data) != 0 {
+            match Value::get(data) {
+                Ok(value) => children.push(value),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        proton_sys::pn_data_exit(data);
+    }
+
+    result.map(|_| children)
+}
+
+/// Whether `value` can be in the same array as `first`.
+fn same_element_type(first: &Value, value: &Value) -> bool {
+    match (first, value) {
+        (&Value::Described(ref d1, ref v1), &Value::Described(ref d2, ref v2)) => {
+            d1 == d2 && v1.pn_type() == v2.pn_type()
+        },
+        (&Value::Described(..), _) | (_, &Value::Described(..)) => false,
+        _ => first.pn_type() == value.pn_type()
+    }
+}
+
+impl Value {
+    /// Returns the AMQP type of the value.
+    pub fn pn_type(&self) -> proton_sys::pn_type_t {
+        match *self {
+            Value::Null => proton_sys::PN_NULL,
+            Value::Bool(_) => proton_sys::PN_BOOL,
+            Value::Ubyte(_) => proton_sys::PN_UBYTE,
+            Value::Ushort(_) => proton_sys::PN_USHORT,
+            Value::Uint(_) => proton_sys::PN_UINT,
+            Value::Ulong(_) => proton_sys::PN_ULONG,
+            Value::Byte(_) => proton_sys::PN_BYTE,
+            Value::Short(_) => proton_sys::PN_SHORT,
+            Value::Int(_) => proton_sys::PN_INT,
+            Value::Long(_) => proton_sys::PN_LONG,
+            Value::Float(_) => proton_sys::PN_FLOAT,
+            Value::Double(_) => proton_sys::PN_DOUBLE,
+            Value::Char(_) => proton_sys::PN_CHAR,
+            Value::Timestamp(_) => proton_sys::PN_TIMESTAMP,
+            Value::Decimal32(_) => proton_sys::PN_DECIMAL32,
+            Value::Decimal64(_) => proton_sys::PN_DECIMAL64,
+            Value::Decimal128(_) => proton_sys::PN_DECIMAL128,
+            Value::Uuid(_) => proton_sys::PN_UUID,
+            Value::Binary(_) => proton_sys::PN_BINARY,
+            Value::String(_) => proton_sys::PN_STRING,
+            Value::Symbol(_) => proton_sys::PN_SYMBOL,
+            Value::List(_) => proton_sys::PN_LIST,
+            Value::Map(_) => proton_sys::PN_MAP,
+            Value::Array(_) => proton_sys::PN_ARRAY,
+            Value::Described(_, _) => proton_sys::PN_DESCRIBED,
+        }
+    }
+
+    /// Checks the value can be put in a `pn_data_t`: the elements of an
+    /// array must all be of the same type, and have the same descriptor in
+    /// a described array.
+    pub fn validate(&self) -> DecodeResult<()> {
+        match *self {
+            Value::List(ref values) => {
+                for value in values.iter() {
+                    try!(value.validate());
+                }
+            },
+            Value::Map(ref entries) => {
+                for &(ref key, ref value) in entries.iter() {
+                    try!(key.validate());
+                    try!(value.validate());
+                }
+            },
+            Value::Array(ref values) => {
+                if let Some(first) = values.first() {
+                    for value in values.iter() {
+                        if !same_element_type(first, value) {
+                            return Err(DecoderError::ApplicationError(
+                                "array elements differ in type or descriptor".to_string()));
+                        }
+                        try!(value.validate());
+                    }
+                }
+            },
+            Value::Described(ref descriptor, ref value) => {
+                try!(descriptor.validate());
+                try!(value.validate());
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Puts the value in `data` at the current position. Nothing is put if
+    /// the value isn't valid, see `validate`.
+    pub fn put(&self, data: *mut proton_sys::pn_data_t) -> DecodeResult<()> {
+        try!(self.validate());
+        unsafe {self.write(data)};
+        Ok(())
+    }
+
+    /// Puts the value as it is, once validated.
+    unsafe fn write(&self, data: *mut proton_sys::pn_data_t) {
+        match *self {
+            Value::Null => {proton_sys::pn_data_put_null(data);},
+            Value::Bool(v) => {proton_sys::pn_data_put_bool(data, v as u8);},
+            Value::Ubyte(v) => {proton_sys::pn_data_put_ubyte(data, v);},
+            Value::Ushort(v) => {proton_sys::pn_data_put_ushort(data, v);},
+            Value::Uint(v) => {proton_sys::pn_data_put_uint(data, v);},
+            Value::Ulong(v) => {proton_sys::pn_data_put_ulong(data, v);},
+            Value::Byte(v) => {proton_sys::pn_data_put_byte(data, v);},
+            Value::Short(v) => {proton_sys::pn_data_put_short(data, v);},
+            Value::Int(v) => {proton_sys::pn_data_put_int(data, v);},
+            Value::Long(v) => {proton_sys::pn_data_put_long(data, v);},
+            Value::Float(v) => {proton_sys::pn_data_put_float(data, v);},
+            Value::Double(v) => {proton_sys::pn_data_put_double(data, v);},
+            Value::Char(v) => {proton_sys::pn_data_put_char(data, v as u32);},
+            Value::Timestamp(v) => {proton_sys::pn_data_put_timestamp(data, v);},
+            Value::Decimal32(v) => {proton_sys::pn_data_put_decimal32(data, v);},
+            Value::Decimal64(v) => {proton_sys::pn_data_put_decimal64(data, v);},
+            Value::Decimal128(ref v) => {
+                let decimal = proton_sys::pn_decimal128_t {bytes: to_c_bytes(v)};
+                proton_sys::pn_data_put_decimal128(data, decimal);
+            },
+            Value::Uuid(ref v) => {
+                proton_sys::pn_data_put_uuid(data, proton_sys::pn_uuid_t {bytes: to_c_bytes(v)});
+            },
+            Value::Binary(ref v) => {proton_sys::pn_data_put_binary(data, pn_bytes(v));},
+            Value::String(ref v) => {proton_sys::pn_data_put_string(data, pn_bytes(v.as_bytes()));},
+            Value::Symbol(ref v) => {proton_sys::pn_data_put_symbol(data, pn_bytes(v.as_bytes()));},
+            Value::List(ref values) => {
+                proton_sys::pn_data_put_list(data);
+                proton_sys::pn_data_enter(data);
+                for value in values.iter() {
+                    value.write(data);
+                }
+                proton_sys::pn_data_exit(data);
+            },
+            Value::Map(ref entries) => {
+                proton_sys::pn_data_put_map(data);
+                proton_sys::pn_data_enter(data);
+                for &(ref key, ref value) in entries.iter() {
+                    key.write(data);
+                    value.write(data);
+                }
+                proton_sys::pn_data_exit(data);
+            },
+            Value::Array(ref values) => {
+                match values.first() {
+                    Some(&Value::Described(ref descriptor, ref first)) => {
+                        proton_sys::pn_data_put_array(data, 1, first.pn_type());
+                        proton_sys::pn_data_enter(data);
+                        descriptor.write(data);
+                    },
+                    Some(first) => {
+                        proton_sys::pn_data_put_array(data, 0, first.pn_type());
+                        proton_sys::pn_data_enter(data);
+                    },
+                    None => {
+                        proton_sys::pn_data_put_array(data, 0, proton_sys::PN_NULL);
+                        proton_sys::pn_data_enter(data);
+                    }
+                }
+
+                for value in values.iter() {
+                    match *value {
+                        Value::Described(_, ref value) => value.write(data),
+                        ref value => value.write(data)
+                    }
+                }
+                proton_sys::pn_data_exit(data);
+            },
+            Value::Described(ref descriptor, ref value) => {
+                proton_sys::pn_data_put_described(data);
+                proton_sys::pn_data_enter(data);
+                descriptor.write(data);
+                value.write(data);
+                proton_sys::pn_data_exit(data);
+            },
+        }
+    }
+
+    /// Reads the first value in `data`, if there's one. Fails if it can't
+    /// be decoded.
+    pub fn from_data(data: *mut proton_sys::pn_data_t) -> DecodeResult<Option<Value>> {
+        unsafe {
+            proton_sys::pn_data_rewind(data);
+            if proton_sys::pn_data_next(data) == 0 {
+                return Ok(None);
+            }
+        }
+        Value::get(data).map(Some)
+    }
+
+    /// Replaces the contents of `data` with `value`, or leaves it empty.
+    /// `data` is left as it was if the value isn't valid.
+    pub fn set_data(data: *mut proton_sys::pn_data_t, value: Option<&Value>) -> DecodeResult<()> {
+        if let Some(value) = value {
+            try!(value.validate());
+        }
+
+        unsafe {
+            proton_sys::pn_data_clear(data);
+            if let Some(value) = value {
+                value.write(data);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the value the cursor of `data` is on.
+    pub fn get(data: *mut proton_sys::pn_data_t) -> DecodeResult<Value> {
+        let t = unsafe{proton_sys::pn_data_type(data)};
+        unsafe {
+            Ok(match t {
+                proton_sys::PN_NULL => Value::Null,
+                proton_sys::PN_BOOL => Value::Bool(proton_sys::pn_data_get_bool(data) != 0),
+                proton_sys::PN_UBYTE => Value::Ubyte(proton_sys::pn_data_get_ubyte(data)),
+                proton_sys::PN_USHORT => Value::Ushort(proton_sys::pn_data_get_ushort(data)),
+                proton_sys::PN_UINT => Value::Uint(proton_sys::pn_data_get_uint(data)),
+                proton_sys::PN_ULONG => Value::Ulong(proton_sys::pn_data_get_ulong(data)),
+                proton_sys::PN_BYTE => Value::Byte(proton_sys::pn_data_get_byte(data)),
+                proton_sys::PN_SHORT => Value::Short(proton_sys::pn_data_get_short(data)),
+                proton_sys::PN_INT => Value::Int(proton_sys::pn_data_get_int(data)),
+                proton_sys::PN_LONG => Value::Long(proton_sys::pn_data_get_long(data)),
+                proton_sys::PN_FLOAT => Value::Float(proton_sys::pn_data_get_float(data)),
+                proton_sys::PN_DOUBLE => Value::Double(proton_sys::pn_data_get_double(data)),
+                proton_sys::PN_CHAR => {
+                    let c = proton_sys::pn_data_get_char(data);
+                    match char::from_u32(c) {
+                        Some(c) => Value::Char(c),
+                        None => return Err(DecoderError::ExpectedError("char".to_string(),
+                                                                       c.to_string()))
+                    }
+                },
+                proton_sys::PN_TIMESTAMP => Value::Timestamp(proton_sys::pn_data_get_timestamp(data)),
+                proton_sys::PN_DECIMAL32 => Value::Decimal32(proton_sys::pn_data_get_decimal32(data)),
+                proton_sys::PN_DECIMAL64 => Value::Decimal64(proton_sys::pn_data_get_decimal64(data)),
+                proton_sys::PN_DECIMAL128 => {
+                    Value::Decimal128(from_c_bytes(&proton_sys::pn_data_get_decimal128(data).bytes))
+                },
+                proton_sys::PN_UUID => Value::Uuid(from_c_bytes(&proton_sys::pn_data_get_uuid(data).bytes)),
+                proton_sys::PN_BINARY => {
+                    Value::Binary(bytes_as_slice(proton_sys::pn_data_get_binary(data)).to_vec())
+                },
+                proton_sys::PN_STRING => Value::String(try!(to_string(proton_sys::pn_data_get_string(data)))),
+                proton_sys::PN_SYMBOL => Value::Symbol(try!(to_string(proton_sys::pn_data_get_symbol(data)))),
+                proton_sys::PN_LIST => Value::List(try!(get_children(data))),
+                proton_sys::PN_MAP => {
+                    let mut children = try!(get_children(data)).into_iter();
+                    let mut entries = Vec::new();
+                    while let Some(key) = children.next() {
+                        entries.push((key, children.next().unwrap_or(Value::Null)));
+                    }
+                    Value::Map(entries)
+                },
+                proton_sys::PN_ARRAY => {
+                    let described = proton_sys::pn_data_is_array_described(data) != 0;
+                    let mut children = try!(get_children(data));
+                    if described && !children.is_empty() {
+                        let descriptor = children.remove(0);
+                        children = children.into_iter().map(|value| {
+                            Value::Described(Box::new(descriptor.clone()), Box::new(value))
+                        }).collect();
+                    }
+                    Value::Array(children)
+                },
+                proton_sys::PN_DESCRIBED => {
+                    let mut children = try!(get_children(data)).into_iter();
+                    match (children.next(), children.next()) {
+                        (Some(descriptor), Some(value)) => {
+                            Value::Described(Box::new(descriptor), Box::new(value))
+                        },
+                        _ => return Err(DecoderError::ExpectedError("descriptor and value".to_string(),
+                                                                    "PN_DESCRIBED".to_string()))
+                    }
+                },
+                _ => return Err(DecoderError::ExpectedError("AMQP value".to_string(), t.to_string()))
+            })
+        }
+    }
+}
+
+impl Encodable for Value {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        let idx = VARIANTS.iter().position(|n| Some(*n) == self.variant_name()).unwrap();
+
+        macro_rules! emit {
+            ($f:expr) => (
+                s.emit_enum(AMQP_TYPE, |s| {
+                    s.emit_enum_variant(VARIANTS[idx], idx, 1, |s| s.emit_enum_variant_arg(0, $f))
+                })
+            )
+        }
+
+        match *self {
+            Value::Null => s.emit_enum(AMQP_TYPE, |s| s.emit_enum_variant("Null", idx, 0, |_| Ok(()))),
+            Value::Bool(v) => emit!(|s| s.emit_bool(v)),
+            Value::Ubyte(v) => emit!(|s| s.emit_u8(v)),
+            Value::Ushort(v) => emit!(|s| s.emit_u16(v)),
+            Value::Uint(v) => emit!(|s| s.emit_u32(v)),
+            Value::Ulong(v) => emit!(|s| s.emit_u64(v)),
+            Value::Byte(v) => emit!(|s| s.emit_i8(v)),
+            Value::Short(v) => emit!(|s| s.emit_i16(v)),
+            Value::Int(v) => emit!(|s| s.emit_i32(v)),
+            Value::Long(v) => emit!(|s| s.emit_i64(v)),
+            Value::Float(v) => emit!(|s| s.emit_f32(v)),
+            Value::Double(v) => emit!(|s| s.emit_f64(v)),
+            Value::Char(v) => emit!(|s| s.emit_char(v)),
+            Value::Timestamp(v) => emit!(|s| s.emit_i64(v)),
+            Value::Decimal32(v) => emit!(|s| s.emit_u32(v)),
+            Value::Decimal64(v) => emit!(|s| s.emit_u64(v)),
+            Value::Decimal128(ref v) => emit!(|s| v[..].encode(s)),
+            Value::Uuid(ref v) => emit!(|s| v[..].encode(s)),
+            Value::Binary(ref v) => emit!(|s| v.encode(s)),
+            Value::String(ref v) => emit!(|s| s.emit_str(v)),
+            Value::Symbol(ref v) => emit!(|s| s.emit_str(v)),
+            Value::List(ref v) => emit!(|s| v.encode(s)),
+            Value::Array(ref v) => emit!(|s| v.encode(s)),
+            Value::Map(ref entries) => emit!(|s| {
+                s.emit_map(entries.len(), |s| {
+                    for (i, &(ref key, ref value)) in entries.iter().enumerate() {
+                        try!(s.emit_map_elt_key(i, |s| key.encode(s)));
+                        try!(s.emit_map_elt_val(i, |s| value.encode(s)));
+                    }
+                    Ok(())
+                })
+            }),
+            Value::Described(ref descriptor, ref value) => {
+                s.emit_enum(AMQP_TYPE, |s| {
+                    s.emit_enum_variant("Described", idx, 2, |s| {
+                        try!(s.emit_enum_variant_arg(0, |s| descriptor.encode(s)));
+                        s.emit_enum_variant_arg(1, |s| value.encode(s))
+                    })
+                })
+            },
+        }
+    }
+}
+
+impl Value {
+    fn variant_name(&self) -> Option<&'static str> {
+        super::encoder::variant_for_type(self.pn_type())
+    }
+}
+
+/// Reads a sequence of exactly 16 bytes, as used by uuids and decimal128.
+pub fn read_16<D: Decoder>(d: &mut D) -> Result<[u8; 16], D::Error> {
+    let bytes: Vec<u8> = try!(Decodable::decode(d));
+    if bytes.len() != 16 {
+        return Err(d.error(&format!("expected 16 bytes, found {}", bytes.len())));
+    }
+
+    let mut array = [0; 16];
+    for (dst, src) in array.iter_mut().zip(bytes.iter()) {
+        *dst = *src;
+    }
+    Ok(array)
+}
+
+impl Decodable for Value {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Value, D::Error> {
+        d.read_enum(AMQP_TYPE, |d| {
+            d.read_enum_variant(&VARIANTS, |d, idx| {
+                macro_rules! arg {
+                    ($f:expr) => (try!(d.read_enum_variant_arg(0, $f)))
+                }
+
+                Ok(match VARIANTS[idx] {
+                    "Null" => Value::Null,
+                    "Bool" => Value::Bool(arg!(|d| d.read_bool())),
+                    "Ubyte" => Value::Ubyte(arg!(|d| d.read_u8())),
+                    "Ushort" => Value::Ushort(arg!(|d| d.read_u16())),
+                    "Uint" => Value::Uint(arg!(|d| d.read_u32())),
+                    "Ulong" => Value::Ulong(arg!(|d| d.read_u64())),
+                    "Byte" => Value::Byte(arg!(|d| d.read_i8())),
+                    "Short" => Value::Short(arg!(|d| d.read_i16())),
+                    "Int" => Value::Int(arg!(|d| d.read_i32())),
+                    "Long" => Value::Long(arg!(|d| d.read_i64())),
+                    "Float" => Value::Float(arg!(|d| d.read_f32())),
+                    "Double" => Value::Double(arg!(|d| d.read_f64())),
+                    "Char" => Value::Char(arg!(|d| d.read_char())),
+                    "Timestamp" => Value::Timestamp(arg!(|d| d.read_i64())),
+                    "Decimal32" => Value::Decimal32(arg!(|d| d.read_u32())),
+                    "Decimal64" => Value::Decimal64(arg!(|d| d.read_u64())),
+                    "Decimal128" => Value::Decimal128(arg!(read_16)),
+                    "Uuid" => Value::Uuid(arg!(read_16)),
+                    "Binary" => Value::Binary(arg!(Decodable::decode)),
+                    "String" => Value::String(arg!(|d| d.read_str())),
+                    "Symbol" => Value::Symbol(arg!(|d| d.read_str())),
+                    "List" => Value::List(arg!(Decodable::decode)),
+                    "Array" => Value::Array(arg!(Decodable::decode)),
+                    "Map" => Value::Map(arg!(|d| {
+                        d.read_map(|d, len| {
+                            let mut entries = Vec::with_capacity(len);
+                            for i in 0..len {
+                                let key = try!(d.read_map_elt_key(i, Decodable::decode));
+                                let value = try!(d.read_map_elt_val(i, Decodable::decode));
+                                entries.push((key, value));
+                            }
+                            Ok(entries)
+                        })
+                    })),
+                    "Described" => {
+                        let descriptor = try!(d.read_enum_variant_arg(0, Decodable::decode));
+                        let value = try!(d.read_enum_variant_arg(1, Decodable::decode));
+                        Value::Described(Box::new(descriptor), Box::new(value))
+                    },
+                    name => return Err(d.error(&format!("unknown AMQP type {}", name)))
+                })
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::encoder::encode;
+    use super::super::decoder::decode;
+    use proton_sys;
+
+    fn sample() -> Value {
+        Value::Map(vec![
+            (Value::Symbol("x-opt-ttl".to_string()), Value::Uint(3000)),
+            (Value::String("flags".to_string()), Value::List(vec![
+                Value::Bool(true), Value::Null, Value::Double(1.5), Value::Char('x'),
+            ])),
+            (Value::Ulong(7), Value::Array(vec![Value::Int(1), Value::Int(2)])),
+            (Value::String("raw".to_string()), Value::Binary(vec![0, 1, 2, 0])),
+            (Value::String("sent".to_string()), Value::Timestamp(1436000000000)),
+            (Value::String("id".to_string()), Value::Uuid([7; 16])),
+            (Value::String("price".to_string()), Value::Decimal64(42)),
+            (Value::String("order".to_string()), Value::Described(
+                Box::new(Value::Symbol("com.example:order".to_string())),
+                Box::new(Value::List(vec![Value::Long(-1), Value::String("a\0b".to_string())])))),
+        ])
+    }
+
+    #[test]
+    fn test_data_round_trip() {
+        let value = sample();
+        let data = unsafe{proton_sys::pn_data(16)};
+        value.put(data).unwrap();
+
+        unsafe {
+            proton_sys::pn_data_rewind(data);
+            assert!(proton_sys::pn_data_next(data) != 0);
+        }
+        assert_eq!(value, Value::get(data).unwrap());
+        unsafe {proton_sys::pn_data_free(data)};
+    }
+
+    #[test]
+    fn test_put_mixed_array() {
+        let data = unsafe{proton_sys::pn_data(16)};
+        let mixed = Value::Array(vec![Value::Int(1), Value::String("a".to_string())]);
+        assert!(Value::List(vec![mixed]).put(data).is_err());
+
+        let descriptors = Value::Array(vec![
+            Value::Described(Box::new(Value::Ulong(0x24)), Box::new(Value::Uint(1))),
+            Value::Described(Box::new(Value::Ulong(0x25)), Box::new(Value::Uint(2))),
+        ]);
+        assert!(descriptors.put(data).is_err());
+        assert_eq!(0, unsafe {proton_sys::pn_data_size(data)});
+
+        // the data is left as it was
+        Value::set_data(data, Some(&Value::Int(1))).unwrap();
+        assert!(Value::set_data(data, Some(&descriptors)).is_err());
+        assert_eq!(Some(Value::Int(1)), Value::from_data(data).unwrap());
+        unsafe {proton_sys::pn_data_free(data)};
+    }
+
+    #[test]
+    fn test_described_array_round_trip() {
+        let descriptor = Value::Ulong(0x77);
+        let value = Value::Array(vec![
+            Value::Described(Box::new(descriptor.clone()), Box::new(Value::String("a".to_string()))),
+            Value::Described(Box::new(descriptor.clone()), Box::new(Value::String("b".to_string()))),
+        ]);
+        let data = unsafe{proton_sys::pn_data(16)};
+        value.put(data).unwrap();
+
+        unsafe {
+            proton_sys::pn_data_rewind(data);
+            proton_sys::pn_data_next(data);
+            assert!(proton_sys::pn_data_is_array_described(data) != 0);
+        }
+        assert_eq!(value, Value::get(data).unwrap());
+        unsafe {proton_sys::pn_data_free(data)};
+    }
+
+    #[test]
+    fn test_described_array_encoding() {
+        let descriptor = Value::Symbol("com.example:point".to_string());
+        let value = Value::Array(vec![
+            Value::Described(Box::new(descriptor.clone()), Box::new(Value::Int(1))),
+            Value::Described(Box::new(descriptor.clone()), Box::new(Value::Int(2))),
+        ]);
+        let encoded = encode(&value).unwrap();
+        assert_eq!(value, decode::<Value>(&encoded).unwrap());
+
+        // elements can still be read without their descriptor
+        assert_eq!(vec![1, 2], decode::<Vec<i32>>(&encoded).unwrap());
+
+        let mixed = Value::Array(vec![
+            Value::Described(Box::new(descriptor.clone()), Box::new(Value::Int(1))),
+            Value::Described(Box::new(Value::Ulong(7)), Box::new(Value::Int(2))),
+        ]);
+        assert!(encode(&mixed).is_err());
+    }
+
+    #[test]
+    fn test_encoding_round_trip() {
+        let value = sample();
+        let encoded = encode(&value).unwrap();
+        assert_eq!(value, decode::<Value>(&encoded).unwrap());
+    }
+
+    #[test]
+    fn test_encoding_matches_data() {
+        let value = sample();
+        let encoded = encode(&value).unwrap();
+
+        let data = unsafe{proton_sys::pn_data(16)};
+        unsafe {
+            proton_sys::pn_data_decode(data,
+                                       encoded.as_ptr() as *const ::libc::c_char,
+                                       encoded.len() as proton_sys::size_t);
+            proton_sys::pn_data_rewind(data);
+            proton_sys::pn_data_next(data);
+        }
+        assert_eq!(value, Value::get(data).unwrap());
+        unsafe {proton_sys::pn_data_free(data)};
+    }
+
+    #[test]
+    fn test_decode_plain_data() {
+        let encoded = encode(&vec![(1u8, "one".to_string())]).unwrap();
+        assert_eq!(Value::List(vec![Value::List(vec![Value::Ubyte(1), Value::String("one".to_string())])]),
+                   decode::<Value>(&encoded).unwrap());
+    }
+}